@@ -8,7 +8,7 @@ use std::sync::mpsc::{channel};
 
 use cssparser::{RGBA};
 use euclid::{Point2D, Size2D, Rect};
-use rustcanvas::{create_canvas, CanvasContextType, FillOrStrokeStyle, CanvasMsg, Canvas2dMsg};
+use rustcanvas::{create_canvas, CanvasContextType, FillOrStrokeStyle, FillRule, CanvasMsg, Canvas2dMsg};
 
 fn main() {
   let (sender, receiver) = channel::<Vec<u8>>();
@@ -31,7 +31,7 @@ fn main() {
       rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::Stroke)).unwrap();
       rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::SetFillStyle(FillOrStrokeStyle::Color(RGBA::new(233, 193, 127, 255))))).unwrap();
       rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::Arc(Point2D::new(700.0, 600.0), 400.0, 0.0, 2.0 * PI as f32, false))).unwrap();
-      rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::Fill)).unwrap();
+      rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::Fill(FillRule::Nonzero))).unwrap();
       rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::SetFillStyle(FillOrStrokeStyle::Color(RGBA::new(0, 0, 0, 255))))).unwrap();
       rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::SetFontStyle("200px \"PingFang TC\"".to_string()))).unwrap();
       rrenderer.send(CanvasMsg::Canvas2d(Canvas2dMsg::FillText("哈哈".to_string(), 1000.0, 800.0, Some(200.0)))).unwrap();
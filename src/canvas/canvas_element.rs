@@ -3,6 +3,7 @@ use std::sync::mpsc::{Sender};
 use euclid::{Size2D};
 
 use super::canvas_trait::{CanvasMsg};
+use super::canvas_rendering_context_2d::{CanvasRenderingContext2D};
 use super::context_2d::{Context2d};
 
 pub struct CanvasElement {
@@ -29,6 +30,10 @@ impl <'a> CanvasElement {
       _ => None,
     }
   }
+
+  pub fn get_context_2d(&self) -> CanvasRenderingContext2D {
+    CanvasRenderingContext2D::new(self.width, self.height, self.ctx.clone())
+  }
 }
 
 #[cfg(test)]
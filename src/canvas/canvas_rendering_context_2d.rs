@@ -0,0 +1,471 @@
+use std::path::{Path};
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use cssparser::{RGBA};
+use euclid::{Point2D, Rect, Size2D, Transform2D, Vector2D};
+
+use super::canvas_trait::*;
+use super::error::{CanvasError};
+use super::paintstate::{FontFace};
+use super::{read_font_file};
+use super::path2d::{Path2D};
+
+/// A typed handle over the `CanvasMsg` channel of a 2d canvas.
+/// Every method maps to one `Canvas2dMsg`, queries block until the canvas thread replies.
+/// Methods only fail with `CanvasError::Disconnected`, errors raised while painting
+/// are delivered to the receivers returned by `errors`.
+#[derive(Clone)]
+pub struct CanvasRenderingContext2D {
+  pub width: i32,
+  pub height: i32,
+  renderer: Sender<CanvasMsg>,
+}
+
+impl CanvasRenderingContext2D {
+  pub fn new(width: i32, height: i32, renderer: Sender<CanvasMsg>) -> CanvasRenderingContext2D {
+    CanvasRenderingContext2D { width, height, renderer }
+  }
+
+  fn send(&self, message: Canvas2dMsg) -> Result<(), CanvasError> {
+    self.renderer.send(CanvasMsg::Canvas2d(message)).map_err(|_| CanvasError::Disconnected)
+  }
+
+  fn query<T, F>(&self, message: F) -> Result<T, CanvasError> where F: FnOnce(Sender<T>) -> Canvas2dMsg {
+    let (sender, receiver) = channel::<T>();
+    self.send(message(sender))?;
+    receiver.recv().map_err(|_| CanvasError::Disconnected)
+  }
+
+  fn query_script<T, F>(&self, message: F) -> Result<T, CanvasError> where F: FnOnce(Sender<T>) -> FromScriptMsg {
+    let (sender, receiver) = channel::<T>();
+    self.renderer.send(CanvasMsg::FromScript(message(sender)))
+      .map_err(|_| CanvasError::Disconnected)?;
+    receiver.recv().map_err(|_| CanvasError::Disconnected)
+  }
+
+  pub fn close(&self) -> Result<(), CanvasError> {
+    self.renderer.send(CanvasMsg::Close).map_err(|_| CanvasError::Disconnected)
+  }
+
+  /// Subscribes to the errors raised by the canvas thread from now on.
+  pub fn errors(&self) -> Result<Receiver<CanvasError>, CanvasError> {
+    let (sender, receiver) = channel::<CanvasError>();
+    self.renderer.send(CanvasMsg::FromScript(FromScriptMsg::SubscribeErrors(sender)))
+      .map_err(|_| CanvasError::Disconnected)?;
+    Ok(receiver)
+  }
+
+  /// Registers a font only visible to this canvas, see `register_font` for every canvas.
+  pub fn register_font(&self, data: Vec<u8>, face: &FontFace) -> Result<(), CanvasError> {
+    self.query_script(|sender| FromScriptMsg::RegisterFont(data, face.clone(), sender))?
+  }
+
+  pub fn register_font_file<P: AsRef<Path>>(&self, path: P, face: &FontFace) -> Result<(), CanvasError> {
+    self.register_font(read_font_file(path)?, face)
+  }
+
+  /// Hit, miss and eviction counters of the glyph outline cache.
+  pub fn glyph_cache_stats(&self) -> Result<GlyphCacheStats, CanvasError> {
+    self.query_script(|sender| FromScriptMsg::GlyphCacheStats(sender))
+  }
+
+  /// Maximum number of glyph outlines kept by the canvas, 0 disables the cache.
+  pub fn set_glyph_cache_limit(&self, limit: usize) -> Result<(), CanvasError> {
+    self.renderer.send(CanvasMsg::FromScript(FromScriptMsg::SetGlyphCacheLimit(limit)))
+      .map_err(|_| CanvasError::Disconnected)
+  }
+
+  /// Encodes the whole canvas, like `canvas.toBuffer()` in node-canvas.
+  pub fn to_buffer(&self, format: ImageFormat) -> Result<Vec<u8>, CanvasError> {
+    self.query_script(|sender| FromScriptMsg::ToBuffer(format, sender))?
+  }
+
+  /// Encodes the whole canvas into a `data:` url, like `canvas.toDataURL()`.
+  pub fn to_data_url(&self, format: ImageFormat) -> Result<String, CanvasError> {
+    self.query_script(|sender| FromScriptMsg::ToDataURL(format, sender))?
+  }
+
+  pub fn save(&self) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SaveContext)
+  }
+
+  pub fn restore(&self) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::RestoreContext)
+  }
+
+  pub fn fill_text(&self, text: &str, x: f32, y: f32, max_width: Option<f32>) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::FillText(text.to_string(), x, y, max_width))
+  }
+
+  pub fn stroke_text(&self, text: &str, x: f32, y: f32, max_width: Option<f32>) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::StrokeText(text.to_string(), x, y, max_width))
+  }
+
+  pub fn fill_rect(&self, x: f32, y: f32, width: f32, height: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::FillRect(Rect::new(Point2D::new(x, y), Size2D::new(width, height))))
+  }
+
+  pub fn stroke_rect(&self, x: f32, y: f32, width: f32, height: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::StrokeRect(Rect::new(Point2D::new(x, y), Size2D::new(width, height))))
+  }
+
+  pub fn clear_rect(&self, x: f32, y: f32, width: f32, height: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::ClearRect(Rect::new(Point2D::new(x, y), Size2D::new(width, height))))
+  }
+
+  pub fn begin_path(&self) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::BeginPath)
+  }
+
+  pub fn close_path(&self) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::ClosePath)
+  }
+
+  pub fn fill(&self, fill_rule: FillRule) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::Fill(fill_rule))
+  }
+
+  pub fn stroke(&self) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::Stroke)
+  }
+
+  pub fn clip(&self, fill_rule: FillRule) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::Clip(fill_rule))
+  }
+
+  pub fn is_point_in_path(&self, x: f64, y: f64, fill_rule: FillRule) -> Result<bool, CanvasError> {
+    self.query(|sender| Canvas2dMsg::IsPointInPath(x, y, fill_rule, sender))
+  }
+
+  pub fn fill_path(&self, path: &Path2D, fill_rule: FillRule) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::FillPath2D(path.clone(), fill_rule))
+  }
+
+  pub fn stroke_path(&self, path: &Path2D) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::StrokePath2D(path.clone()))
+  }
+
+  pub fn clip_path(&self, path: &Path2D, fill_rule: FillRule) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::ClipPath2D(path.clone(), fill_rule))
+  }
+
+  pub fn is_point_in_path2d(&self, path: &Path2D, x: f64, y: f64, fill_rule: FillRule) -> Result<bool, CanvasError> {
+    let path = path.clone();
+    self.query(|sender| Canvas2dMsg::IsPointInPath2D(path, x, y, fill_rule, sender))
+  }
+
+  /// Checks the point against the current path stroked with the current line styles.
+  pub fn is_point_in_stroke(&self, x: f64, y: f64) -> Result<bool, CanvasError> {
+    self.query(|sender| Canvas2dMsg::IsPointInStroke(x, y, sender))
+  }
+
+  pub fn is_point_in_stroke2d(&self, path: &Path2D, x: f64, y: f64) -> Result<bool, CanvasError> {
+    let path = path.clone();
+    self.query(|sender| Canvas2dMsg::IsPointInStroke2D(path, x, y, sender))
+  }
+
+  pub fn move_to(&self, x: f32, y: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::MoveTo(Point2D::new(x, y)))
+  }
+
+  pub fn line_to(&self, x: f32, y: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::LineTo(Point2D::new(x, y)))
+  }
+
+  pub fn rect(&self, x: f32, y: f32, width: f32, height: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::Rect(Rect::new(Point2D::new(x, y), Size2D::new(width, height))))
+  }
+
+  pub fn quadratic_curve_to(&self, cpx: f32, cpy: f32, x: f32, y: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::QuadraticCurveTo(Point2D::new(cpx, cpy), Point2D::new(x, y)))
+  }
+
+  pub fn bezier_curve_to(&self, cp1x: f32, cp1y: f32, cp2x: f32, cp2y: f32,
+                         x: f32, y: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::BezierCurveTo(Point2D::new(cp1x, cp1y), Point2D::new(cp2x, cp2y), Point2D::new(x, y)))
+  }
+
+  pub fn arc(&self, x: f32, y: f32, radius: f32, start_angle: f32, end_angle: f32,
+             anticlockwise: bool) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::Arc(Point2D::new(x, y), radius, start_angle, end_angle, anticlockwise))
+  }
+
+  pub fn arc_to(&self, x1: f32, y1: f32, x2: f32, y2: f32, radius: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::ArcTo(Point2D::new(x1, y1), Point2D::new(x2, y2), radius))
+  }
+
+  pub fn ellipse(&self, x: f32, y: f32, radius_x: f32, radius_y: f32, rotation: f32,
+                 start_angle: f32, end_angle: f32, anticlockwise: bool) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::Ellipse(Point2D::new(x, y), radius_x, radius_y, rotation, start_angle, end_angle, anticlockwise))
+  }
+
+  pub fn draw_image(&self, image_data: Vec<u8>, image_size: Size2D<f64>,
+                    dest_rect: Rect<f64>, source_rect: Rect<f64>,
+                    smoothing_enabled: bool) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::DrawImage(image_data, image_size, dest_rect, source_rect, smoothing_enabled))
+  }
+
+  pub fn draw_image_self(&self, image_size: Size2D<f64>, dest_rect: Rect<f64>,
+                         source_rect: Rect<f64>, smoothing_enabled: bool) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::DrawImageSelf(image_size, dest_rect, source_rect, smoothing_enabled))
+  }
+
+  /// Returns the RGBA pixels of the given rect.
+  pub fn get_image_data(&self, sx: i32, sy: i32, sw: i32, sh: i32) -> Result<Vec<u8>, CanvasError> {
+    let canvas_size = Size2D::new(self.width as f64, self.height as f64);
+    let rect = Rect::new(Point2D::new(sx, sy), Size2D::new(sw, sh));
+    self.query(|sender| Canvas2dMsg::GetImageData(rect, canvas_size, sender))
+  }
+
+  /// Writes RGBA pixels of `image_data_size` at (dx, dy).
+  pub fn put_image_data(&self, image_data: Vec<u8>, image_data_size: Size2D<f64>,
+                        dx: f64, dy: f64) -> Result<(), CanvasError> {
+    let dirty_rect = Rect::new(Point2D::zero(), image_data_size);
+    self.put_image_data_with_dirty_rect(image_data, image_data_size, dx, dy, dirty_rect)
+  }
+
+  pub fn put_image_data_with_dirty_rect(&self, image_data: Vec<u8>, image_data_size: Size2D<f64>,
+                                        dx: f64, dy: f64, dirty_rect: Rect<f64>) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::PutImageData(image_data, Vector2D::new(dx, dy), image_data_size, dirty_rect))
+  }
+
+  pub fn set_fill_style(&self, style: FillOrStrokeStyle) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetFillStyle(style))
+  }
+
+  pub fn set_stroke_style(&self, style: FillOrStrokeStyle) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetStrokeStyle(style))
+  }
+
+  /// Takes a css color, invalid values leave the style unchanged.
+  pub fn set_fill_style_str(&self, style: &str) -> Result<(), CanvasError> {
+    match style.parse() {
+      Ok(style) => self.set_fill_style(style),
+      Err(()) => Ok(()),
+    }
+  }
+
+  pub fn set_stroke_style_str(&self, style: &str) -> Result<(), CanvasError> {
+    match style.parse() {
+      Ok(style) => self.set_stroke_style(style),
+      Err(()) => Ok(()),
+    }
+  }
+
+  /// Takes a css `font` shorthand, invalid values leave the font unchanged.
+  pub fn set_font(&self, font: &str) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetFontStyle(font.to_string()))
+  }
+
+  /// Measures `text` with the current font, a missing font is reported
+  /// through `errors` and measures as zero.
+  pub fn measure_text(&self, text: &str) -> Result<TextMetrics, CanvasError> {
+    self.query(|sender| Canvas2dMsg::MeasureText(text.to_string(), sender))
+  }
+
+  pub fn set_text_align(&self, align: TextAlign) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetTextAlign(align))
+  }
+
+  pub fn set_text_baseline(&self, baseline: TextBaseline) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetTextBaseline(baseline))
+  }
+
+  pub fn set_direction(&self, direction: Direction) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetDirection(direction))
+  }
+
+  /// A single line of text has no block direction, both vertical modes draw the same.
+  pub fn set_writing_mode(&self, writing_mode: WritingMode) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetWritingMode(writing_mode))
+  }
+
+  pub fn set_line_width(&self, width: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetLineWidth(width))
+  }
+
+  pub fn set_line_cap(&self, cap: LineCapStyle) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetLineCap(cap))
+  }
+
+  pub fn set_line_join(&self, join: LineJoinStyle) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetLineJoin(join))
+  }
+
+  pub fn set_miter_limit(&self, limit: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetMiterLimit(limit))
+  }
+
+  /// Segments with a negative or non finite value are ignored, odd length lists are repeated.
+  pub fn set_line_dash(&self, segments: &[f32]) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetLineDash(segments.to_vec()))
+  }
+
+  pub fn get_line_dash(&self) -> Result<Vec<f32>, CanvasError> {
+    self.query(|sender| Canvas2dMsg::GetLineDash(sender))
+  }
+
+  pub fn set_line_dash_offset(&self, offset: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetLineDashOffset(offset))
+  }
+
+  pub fn set_global_alpha(&self, alpha: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetGlobalAlpha(alpha))
+  }
+
+  pub fn set_global_composite_operation(&self, op: CompositionOrBlending) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetGlobalComposition(op))
+  }
+
+  pub fn set_transform(&self, a: f32, b: f32, c: f32, d: f32, e: f32, f: f32) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetTransform(Transform2D::row_major(a, b, c, d, e, f)))
+  }
+
+  pub fn reset_transform(&self) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetTransform(Transform2D::identity()))
+  }
+
+  pub fn set_shadow_offset_x(&self, value: f64) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetShadowOffsetX(value))
+  }
+
+  pub fn set_shadow_offset_y(&self, value: f64) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetShadowOffsetY(value))
+  }
+
+  pub fn set_shadow_blur(&self, value: f64) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetShadowBlur(value))
+  }
+
+  pub fn set_shadow_color(&self, color: RGBA) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetShadowColor(color))
+  }
+
+  /// Takes a css color, invalid values leave the shadow color unchanged.
+  pub fn set_shadow_color_str(&self, color: &str) -> Result<(), CanvasError> {
+    match color.parse::<ShadowColor>() {
+      Ok(color) => self.set_shadow_color(color.into()),
+      Err(()) => Ok(()),
+    }
+  }
+
+  /// Takes a css `filter` like `blur(2px) grayscale(100%)`, invalid values leave
+  /// the filter unchanged.
+  pub fn set_filter(&self, filter: &str) -> Result<(), CanvasError> {
+    self.send(Canvas2dMsg::SetFilter(filter.to_string()))
+  }
+}
+
+#[cfg(test)]
+mod canvas_rendering_context_2d_tests {
+  use super::*;
+  use canvas::{create_canvas, CanvasContextType};
+
+  #[test]
+  fn should_get_image_data_from_context() {
+    let ctx = create_canvas(100, 50, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_fill_style(FillOrStrokeStyle::Color(RGBA::new(255, 0, 0, 255))).unwrap();
+    ctx.fill_rect(0.0, 0.0, 100.0, 50.0).unwrap();
+    let pixels = ctx.get_image_data(0, 0, 100, 50).unwrap();
+    assert_eq!(pixels.len(), 100 * 50 * 4);
+    assert_eq!(&pixels[0..4], &[255, 0, 0, 255]);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_ignore_invalid_color_strings() {
+    let ctx = create_canvas(10, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_fill_style_str("hsl(240, 100%, 50%)").unwrap();
+    ctx.set_fill_style_str("not a color").unwrap();
+    ctx.fill_rect(0.0, 0.0, 10.0, 10.0).unwrap();
+    assert_eq!(ctx.get_image_data(5, 5, 1, 1).unwrap(), vec![0, 0, 255, 255]);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_check_point_in_path() {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    ctx.rect(10.0, 10.0, 20.0, 20.0).unwrap();
+    assert!(ctx.is_point_in_path(15.0, 15.0, FillRule::Nonzero).unwrap());
+    assert!(!ctx.is_point_in_path(50.0, 50.0, FillRule::Nonzero).unwrap());
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_encode_canvas_to_png() {
+    let ctx = create_canvas(10, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.fill_rect(0.0, 0.0, 10.0, 10.0).unwrap();
+    let png = ctx.to_buffer(ImageFormat::Png).unwrap();
+    assert_eq!(&png[1..4], b"PNG");
+    let url = ctx.to_data_url(ImageFormat::from_mime_type("image/jpeg", Some(0.5))).unwrap();
+    assert!(url.starts_with("data:image/jpeg;base64,"));
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_report_errors_from_canvas_thread() {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    let errors = ctx.errors().unwrap();
+    let size = Size2D::new(2.0, 2.0);
+    ctx.put_image_data(vec![0; 4], size, 0.0, 0.0).unwrap();
+    assert_eq!(errors.recv().unwrap(), CanvasError::InvalidImageData { expected: 16, actual: 4 });
+    // the canvas is still alive after a bad request
+    assert_eq!(ctx.get_image_data(0, 0, 1, 1).unwrap().len(), 4);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_measure_empty_text() {
+    let ctx = create_canvas(10, 10, CanvasContextType::CTX2D).get_context_2d();
+    let metrics = ctx.measure_text("").unwrap();
+    assert_eq!(metrics.width, 0.0);
+    assert_eq!(metrics.alphabetic_baseline, 0.0);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn baselines_should_be_relative_to_text_baseline() {
+    let ctx = create_canvas(10, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_font("20px sans-serif").unwrap();
+    let alphabetic = ctx.measure_text("Hello").unwrap();
+    ctx.set_text_baseline(TextBaseline::Top).unwrap();
+    let top = ctx.measure_text("Hello").unwrap();
+    assert_eq!(top.width, alphabetic.width);
+    assert_eq!(top.font_bounding_box_ascent, 0.0);
+    // baselines are measured from the top of the em box
+    assert_eq!(top.alphabetic_baseline, -alphabetic.font_bounding_box_ascent);
+    assert_eq!(top.ideographic_baseline, -top.font_bounding_box_descent);
+    assert!(top.hanging_baseline < 0.0 && top.hanging_baseline > top.alphabetic_baseline);
+    assert!(top.actual_bounding_box_ascent <= 0.0);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_reject_invalid_font_file() {
+    let ctx = create_canvas(10, 10, CanvasContextType::CTX2D).get_context_2d();
+    let result = ctx.register_font(vec![0, 1, 0, 0], &FontFace::new("Brand"));
+    assert_eq!(result, Err(CanvasError::FontLoad("Brand".to_string())));
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_report_glyph_cache_stats() {
+    let ctx = create_canvas(10, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_glyph_cache_limit(16).unwrap();
+    let stats = ctx.glyph_cache_stats().unwrap();
+    assert_eq!((stats.len, stats.limit), (0, 16));
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn drawing_text_again_should_hit_glyph_cache() {
+    let ctx = create_canvas(20, 20, CanvasContextType::CTX2D).get_context_2d();
+    ctx.fill_text("a", 0.0, 10.0, None).unwrap();
+    let stats = ctx.glyph_cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.len), (0, 1, 1));
+    ctx.fill_text("a", 0.0, 10.0, None).unwrap();
+    let stats = ctx.glyph_cache_stats().unwrap();
+    assert_eq!((stats.hits, stats.misses, stats.len), (1, 1, 1));
+    ctx.close().unwrap();
+  }
+}
@@ -4,6 +4,13 @@ use std::sync::mpsc::{Sender};
 use cssparser::RGBA;
 use euclid::{Point2D, Rect, Size2D, Transform2D, Vector2D};
 
+use csshelper::{parse_color};
+use fontrenderer::{GlyphCacheStats};
+
+use super::error::{CanvasError};
+use super::paintstate::{FontFace};
+use super::path2d::{Path2D};
+
 #[derive(Clone)]
 pub enum CanvasMsg {
   Canvas2d(Canvas2dMsg),
@@ -13,7 +20,13 @@ pub enum CanvasMsg {
 
 #[derive(Clone)]
 pub enum FromScriptMsg {
+  GlyphCacheStats(Sender<GlyphCacheStats>),
+  RegisterFont(Vec<u8>, FontFace, Sender<Result<(), CanvasError>>),
   SendPixels(Sender<Option<Vec<u8>>>),
+  SetGlyphCacheLimit(usize),
+  SubscribeErrors(Sender<CanvasError>),
+  ToBuffer(ImageFormat, Sender<Result<Vec<u8>, CanvasError>>),
+  ToDataURL(ImageFormat, Sender<Result<String, CanvasError>>),
 }
 
 #[derive(Clone)]
@@ -25,15 +38,22 @@ pub enum Canvas2dMsg {
   BeginPath,
   BezierCurveTo(Point2D<f32>, Point2D<f32>, Point2D<f32>),
   ClearRect(Rect<f32>),
-  Clip,
+  Clip(FillRule),
+  ClipPath2D(Path2D, FillRule),
   ClosePath,
   Ellipse(Point2D<f32>, f32, f32, f32, f32, f32, bool),
-  Fill,
+  Fill(FillRule),
+  FillPath2D(Path2D, FillRule),
   FillText(String, f32, f32, Option<f32>),
   FillRect(Rect<f32>),
   GetImageData(Rect<i32>, Size2D<f64>, Sender<Vec<u8>>),
+  GetLineDash(Sender<Vec<f32>>),
   IsPointInPath(f64, f64, FillRule, Sender<bool>),
+  IsPointInPath2D(Path2D, f64, f64, FillRule, Sender<bool>),
+  IsPointInStroke(f64, f64, Sender<bool>),
+  IsPointInStroke2D(Path2D, f64, f64, Sender<bool>),
   LineTo(Point2D<f32>),
+  MeasureText(String, Sender<TextMetrics>),
   MoveTo(Point2D<f32>),
   PutImageData(Vec<u8>, Vector2D<f64>, Size2D<f64>, Rect<f64>),
   QuadraticCurveTo(Point2D<f32>, Point2D<f32>),
@@ -42,14 +62,18 @@ pub enum Canvas2dMsg {
   SaveContext,
   StrokeRect(Rect<f32>),
   Stroke,
+  StrokePath2D(Path2D),
   StrokeText(String, f32, f32, Option<f32>),
   SetFillStyle(FillOrStrokeStyle),
+  SetFilter(String),
   SetFontStyle(String),
   SetStrokeStyle(FillOrStrokeStyle),
   SetLineWidth(f32),
   SetLineCap(LineCapStyle),
   SetLineJoin(LineJoinStyle),
   SetMiterLimit(f32),
+  SetLineDash(Vec<f32>),
+  SetLineDashOffset(f32),
   SetGlobalAlpha(f32),
   SetGlobalComposition(CompositionOrBlending),
   SetTransform(Transform2D<f32>),
@@ -57,16 +81,69 @@ pub enum Canvas2dMsg {
   SetShadowOffsetY(f64),
   SetShadowBlur(f64),
   SetShadowColor(RGBA),
+  SetTextAlign(TextAlign),
+  SetTextBaseline(TextBaseline),
+  SetDirection(Direction),
+  SetWritingMode(WritingMode),
   // for not implement methods
   NotImplement,
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+/// Output format of `toBuffer` / `toDataURL`, quality is in the range of [0, 1]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ImageFormat {
+  Png,
+  Jpeg(f32),
+  WebP(f32),
+}
+
+impl ImageFormat {
+  // https://html.spec.whatwg.org/multipage/#a-serialisation-of-the-bitmap-as-a-file
+  pub fn from_mime_type(mime_type: &str, quality: Option<f32>) -> ImageFormat {
+    let quality = match quality {
+      Some(q) if q >= 0.0 && q <= 1.0 => q,
+      _ => 0.92,
+    };
+    match mime_type.to_lowercase().as_str() {
+      "image/jpeg" => ImageFormat::Jpeg(quality),
+      "image/webp" => ImageFormat::WebP(quality),
+      _ => ImageFormat::Png,
+    }
+  }
+
+  pub fn mime_type(&self) -> &str {
+    match *self {
+      ImageFormat::Png => "image/png",
+      ImageFormat::Jpeg(_) => "image/jpeg",
+      ImageFormat::WebP(_) => "image/webp",
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum FillRule {
   Nonzero,
   Evenodd,
 }
 
+impl Default for FillRule {
+  fn default() -> FillRule {
+    FillRule::Nonzero
+  }
+}
+
+impl FromStr for FillRule {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<FillRule, ()> {
+    match string {
+      "nonzero" => Ok(FillRule::Nonzero),
+      "evenodd" => Ok(FillRule::Evenodd),
+      _ => Err(()),
+    }
+  }
+}
+
 #[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
 pub enum BlendingStyle {
   Multiply,
@@ -220,10 +297,40 @@ pub enum FillOrStrokeStyle {
   Color(RGBA),
   LinearGradient(LinearGradientStyle),
   RadialGradient(RadialGradientStyle),
+  ConicGradient(ConicGradientStyle),
   Surface(SurfaceStyle),
 }
 
-#[derive(Clone, Deserialize, Serialize)]
+/// Only colors can be written as strings, gradients and patterns are built from their styles.
+impl FromStr for FillOrStrokeStyle {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<FillOrStrokeStyle, ()> {
+    parse_color(string).map(FillOrStrokeStyle::Color)
+  }
+}
+
+/// A css color for `Canvas2dMsg::SetShadowColor`, parsed like the color of a
+/// `FillOrStrokeStyle` so clients of the messages need no parser of their own:
+/// `Canvas2dMsg::SetShadowColor("rgba(0, 0, 0, 0.5)".parse::<ShadowColor>()?.into())`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShadowColor(pub RGBA);
+
+impl FromStr for ShadowColor {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<ShadowColor, ()> {
+    parse_color(string).map(ShadowColor)
+  }
+}
+
+impl From<ShadowColor> for RGBA {
+  fn from(color: ShadowColor) -> RGBA {
+    color.0
+  }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct CanvasGradientStop {
   pub offset: f64,
   pub color: RGBA,
@@ -277,12 +384,35 @@ impl RadialGradientStyle {
   }
 }
 
+/// Colors go clockwise around `(x, y)` from `start_angle`, in radians from the x axis.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ConicGradientStyle {
+  pub start_angle: f64,
+  pub x: f64,
+  pub y: f64,
+  pub stops: Vec<CanvasGradientStop>
+}
+
+impl ConicGradientStyle {
+  pub fn new(start_angle: f64, x: f64, y: f64, stops: Vec<CanvasGradientStop>) -> ConicGradientStyle {
+    ConicGradientStyle {
+      start_angle: start_angle,
+      x: x,
+      y: y,
+      stops: stops,
+    }
+  }
+}
+
+/// Surface pixels in B8G8R8A8, `transform` maps the pattern space to the user space
+/// like `CanvasPattern.setTransform()`.
 #[derive(Clone, Deserialize, Serialize)]
 pub struct SurfaceStyle {
   pub surface_data: Vec<u8>,
   pub surface_size: Size2D<i32>,
   pub repeat_x: bool,
   pub repeat_y: bool,
+  pub transform: Transform2D<f32>,
 }
 
 impl SurfaceStyle {
@@ -293,6 +423,65 @@ impl SurfaceStyle {
       surface_size: surface_size,
       repeat_x: repeat_x,
       repeat_y: repeat_y,
+      transform: Transform2D::identity(),
+    }
+  }
+
+  pub fn with_repetition(surface_data: Vec<u8>, surface_size: Size2D<i32>, repetition: Repetition)
+      -> SurfaceStyle {
+    let (repeat_x, repeat_y) = repetition.repeats();
+    SurfaceStyle::new(surface_data, surface_size, repeat_x, repeat_y)
+  }
+
+  pub fn set_transform(&mut self, transform: Transform2D<f32>) {
+    self.transform = transform;
+  }
+
+  /// Corners of the area painted by a pattern not repeated both ways, in user space.
+  pub fn painted_area(&self) -> Option<[Point2D<f32>; 4]> {
+    // far enough to cover any canvas along the repeated axis
+    const UNBOUNDED: f32 = 1e7;
+    let (x0, x1) = if self.repeat_x { (-UNBOUNDED, UNBOUNDED) } else { (0.0, self.surface_size.width as f32) };
+    let (y0, y1) = if self.repeat_y { (-UNBOUNDED, UNBOUNDED) } else { (0.0, self.surface_size.height as f32) };
+    if self.repeat_x && self.repeat_y {
+      return None;
+    }
+    let corner = |x, y| self.transform.transform_point(&Point2D::new(x, y));
+    Some([corner(x0, y0), corner(x1, y0), corner(x1, y1), corner(x0, y1)])
+  }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Repetition {
+  Repeat,
+  RepeatX,
+  RepeatY,
+  NoRepeat,
+}
+
+impl Repetition {
+  /// Whether the pattern repeats along x and along y.
+  pub fn repeats(&self) -> (bool, bool) {
+    match *self {
+      Repetition::Repeat => (true, true),
+      Repetition::RepeatX => (true, false),
+      Repetition::RepeatY => (false, true),
+      Repetition::NoRepeat => (false, false),
+    }
+  }
+}
+
+impl FromStr for Repetition {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<Repetition, ()> {
+    match string {
+      // https://html.spec.whatwg.org/multipage/#dom-context-2d-createpattern
+      "" | "repeat" => Ok(Repetition::Repeat),
+      "repeat-x" => Ok(Repetition::RepeatX),
+      "repeat-y" => Ok(Repetition::RepeatY),
+      "no-repeat" => Ok(Repetition::NoRepeat),
+      _ => Err(()),
     }
   }
 }
@@ -336,3 +525,189 @@ impl FromStr for LineJoinStyle {
     }
   }
 }
+
+// https://html.spec.whatwg.org/multipage/#textmetrics
+/// Horizontal distances are measured from the `textAlign` point, vertical ones from
+/// the `textBaseline` line, positive values go up and to the right except for the
+/// left and descent distances.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct TextMetrics {
+  pub width: f32,
+  pub actual_bounding_box_left: f32,
+  pub actual_bounding_box_right: f32,
+  pub actual_bounding_box_ascent: f32,
+  pub actual_bounding_box_descent: f32,
+  pub font_bounding_box_ascent: f32,
+  pub font_bounding_box_descent: f32,
+  pub em_height_ascent: f32,
+  pub em_height_descent: f32,
+  pub hanging_baseline: f32,
+  pub alphabetic_baseline: f32,
+  pub ideographic_baseline: f32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TextAlign {
+  Start,
+  End,
+  Left,
+  Right,
+  Center,
+}
+
+impl Default for TextAlign {
+  fn default() -> TextAlign {
+    TextAlign::Start
+  }
+}
+
+impl FromStr for TextAlign {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<TextAlign, ()> {
+    match string {
+      "start" => Ok(TextAlign::Start),
+      "end" => Ok(TextAlign::End),
+      "left" => Ok(TextAlign::Left),
+      "right" => Ok(TextAlign::Right),
+      "center" => Ok(TextAlign::Center),
+      _ => Err(()),
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum TextBaseline {
+  Top,
+  Hanging,
+  Middle,
+  Alphabetic,
+  Ideographic,
+  Bottom,
+}
+
+impl Default for TextBaseline {
+  fn default() -> TextBaseline {
+    TextBaseline::Alphabetic
+  }
+}
+
+impl FromStr for TextBaseline {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<TextBaseline, ()> {
+    match string {
+      "top" => Ok(TextBaseline::Top),
+      "hanging" => Ok(TextBaseline::Hanging),
+      "middle" => Ok(TextBaseline::Middle),
+      "alphabetic" => Ok(TextBaseline::Alphabetic),
+      "ideographic" => Ok(TextBaseline::Ideographic),
+      "bottom" => Ok(TextBaseline::Bottom),
+      _ => Err(()),
+    }
+  }
+}
+
+/// There is no element to inherit from, `Inherit` behaves as `Ltr`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum Direction {
+  Ltr,
+  Rtl,
+  Inherit,
+}
+
+impl Default for Direction {
+  fn default() -> Direction {
+    Direction::Inherit
+  }
+}
+
+impl FromStr for Direction {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<Direction, ()> {
+    match string {
+      "ltr" => Ok(Direction::Ltr),
+      "rtl" => Ok(Direction::Rtl),
+      "inherit" => Ok(Direction::Inherit),
+      _ => Err(()),
+    }
+  }
+}
+
+/// Vertical lines run top to bottom, CJK chars stay upright and other chars
+/// are rotated clockwise, like `text-orientation: mixed`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum WritingMode {
+  HorizontalTb,
+  VerticalRl,
+  VerticalLr,
+}
+
+impl WritingMode {
+  pub fn is_vertical(&self) -> bool {
+    *self != WritingMode::HorizontalTb
+  }
+}
+
+impl Default for WritingMode {
+  fn default() -> WritingMode {
+    WritingMode::HorizontalTb
+  }
+}
+
+impl FromStr for WritingMode {
+  type Err = ();
+
+  fn from_str(string: &str) -> Result<WritingMode, ()> {
+    match string {
+      "horizontal-tb" => Ok(WritingMode::HorizontalTb),
+      "vertical-rl" => Ok(WritingMode::VerticalRl),
+      "vertical-lr" => Ok(WritingMode::VerticalLr),
+      _ => Err(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod canvas_trait_tests {
+  use super::*;
+
+  #[test]
+  fn should_parse_fill_or_stroke_style_color() {
+    match "#ff0000".parse::<FillOrStrokeStyle>() {
+      Ok(FillOrStrokeStyle::Color(color)) => assert_eq!(color, RGBA::new(255, 0, 0, 255)),
+      _ => panic!("should parse a color style"),
+    }
+    assert!("linear-gradient(red, blue)".parse::<FillOrStrokeStyle>().is_err());
+  }
+
+  #[test]
+  fn should_parse_shadow_color() {
+    assert_eq!("blue".parse::<ShadowColor>(), Ok(ShadowColor(RGBA::new(0, 0, 255, 255))));
+    let color: RGBA = "rgba(0, 0, 0, 0.5)".parse::<ShadowColor>().unwrap().into();
+    assert_eq!(color, RGBA::new(0, 0, 0, 128));
+    assert_eq!("not a color".parse::<ShadowColor>(), Err(()));
+  }
+
+  #[test]
+  fn should_parse_repetition() {
+    assert_eq!("".parse::<Repetition>(), Ok(Repetition::Repeat));
+    assert_eq!("repeat-x".parse::<Repetition>().map(|r| r.repeats()), Ok((true, false)));
+    assert_eq!("no-repeat".parse::<Repetition>(), Ok(Repetition::NoRepeat));
+    assert_eq!("Repeat".parse::<Repetition>(), Err(()));
+  }
+
+  #[test]
+  fn painted_area_should_follow_pattern_transform() {
+    let mut style = SurfaceStyle::with_repetition(vec![0; 16], Size2D::new(2, 2), Repetition::NoRepeat);
+    style.set_transform(Transform2D::create_scale(2.0, 3.0).post_translate(Vector2D::new(10.0, 0.0)));
+    assert_eq!(style.painted_area(), Some([
+      Point2D::new(10.0, 0.0), Point2D::new(14.0, 0.0), Point2D::new(14.0, 6.0), Point2D::new(10.0, 6.0),
+    ]));
+    style.repeat_x = true;
+    assert!(style.painted_area().unwrap()[1].x > 1e6);
+    style.repeat_y = true;
+    assert_eq!(style.painted_area(), None);
+  }
+}
@@ -1,9 +1,10 @@
-use std::cell::{RefCell};
+use std::cell::{Cell};
 use std::collections::{BTreeMap};
-use std::collections::btree_map::{Entry};
+use std::f32::consts::{SQRT_2};
 use std::mem;
+use std::ops::{Range};
+use std::rc::{Rc};
 use std::sync::{Arc};
-use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
 use std::sync::mpsc::{Sender, channel};
 use std::thread;
 
@@ -13,52 +14,99 @@ use azure::azure_hl::GradientStop;
 use azure::azure_hl::{Pattern, DrawTarget, SurfaceFormat, DrawSurfaceOptions};
 use azure::azure_hl::{AntialiasMode, CompositionOp, Color, DrawOptions, Filter, ColorPattern};
 use azure::azure_hl::{LinearGradientPattern, ExtendMode, RadialGradientPattern, SurfacePattern};
-use azure::azure_hl::{PathBuilder, CapStyle, StrokeOptions};
+use azure::azure_hl::{Path, PathBuilder, PatternRef, CapStyle, StrokeOptions};
+use azure::azure_hl::{FillRule as AzureFillRule};
 use azure::{AzFloat};
+use azure::azure::{AzAntialiasMode, AzCapStyle, AzCompositionOp, AzDrawOptions, AzJoinStyle, AzStrokeOptions};
+use azure::azure::{AzDrawTargetStroke};
 use euclid::{Rect, Point2D, Vector2D, Transform2D, Size2D};
-use fonts::system_fonts;
 use lyon_path::{PathEvent};
 use num_traits::ToPrimitive;
-use pathfinder_font_renderer::{FontContext, FontInstance, GlyphKey, SubpixelOffset};
+use pathfinder_font_renderer::{FontInstance, GlyphKey, SubpixelOffset};
 
-use fontrenderer::{flip_text};
+use fontrenderer::{flip_text, is_upright, load_shared_family, shared_family_for_char, shared_font_context, shared_font_database};
+use fontrenderer::{visual_runs, FaceId, FontDatabase, FontKey, FontMetrics, Shaper};
+use fontrenderer::{map_points, CachedGlyph, GlyphCache, Synthesis, DEFAULT_GLYPH_CACHE_LIMIT, SMALL_CAPS_SCALE};
 use csshelper::{SANS_SERIF_FONT_FAMILY};
+use imageencoder::{encode, to_data_url};
 use super::canvas_trait::*;
-use super::paintstate::{Font, PaintState};
+use super::error::{CanvasError};
+use super::filter_effects::{apply_filter, filter_extent};
+use super::paintstate::{CanvasFilter, FilterFunction, Font, FontStyle, FontVariant, PaintState};
+use super::path2d::{arc_to_segment, ArcSegment, Path2D, PathOp};
 use super::get_target::{get_draw_target};
+use super::gradient::{render_conic_gradient};
 
-static NEXT_FONT_KEY: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Where a face of `Context2d::font_keys` comes from.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord)]
+enum FontScope {
+  Canvas,
+  Shared,
+}
 
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
-struct FontKey(usize);
+/// A shaped glyph, `x` and `y` are relative to the start of the line on the
+/// alphabetic baseline, y up.
+struct PositionedGlyph {
+  c: char,
+  glyph_id: u32,
+  x: f32,
+  y: f32,
+  /// Turned clockwise, for sideways chars of vertical text.
+  rotated: bool,
+}
 
-impl FontKey {
-  fn new() -> FontKey {
-    FontKey(NEXT_FONT_KEY.fetch_add(1, Ordering::SeqCst))
-  }
+/// Glyphs of consecutive chars drawn with the same face.
+struct TextRun {
+  font_key: FontKey,
+  size: Au,
+  instance: FontInstance<FontKey>,
+  synthesis: Synthesis,
+  glyphs: Vec<PositionedGlyph>,
+}
+
+/// Font, size, glyph id and synthetic styles of a cached outline.
+type GlyphCacheKey = (FontKey, Au, u32, Synthesis);
+
+/// A line of text laid out with the current font, baselines come from the primary face.
+struct TextLayout {
+  runs: Vec<TextRun>,
+  metrics: FontMetrics,
+  width: f32,
 }
 
 pub struct Context2d<'a> {
   pub state: PaintState<'a>,
   saved_states: Vec<PaintState<'a>>,
+  size: Size2D<i32>,
   drawtarget: DrawTarget,
   path_builder: PathBuilder,
-  font_context: RefCell<FontContext<FontKey>>,
-  font_caches: BTreeMap<String, FontKey>,
+  /// User space bounds of the points of the current path, curves lie within
+  /// the bounds of their control points.
+  path_bounds: Cell<Option<Rect<f32>>>,
+  /// Fonts registered for this canvas only, queried before the shared database.
+  local_fonts: FontDatabase,
+  /// Faces loaded in the shared font context.
+  font_keys: BTreeMap<(FontScope, FaceId), FontKey>,
+  font_metrics: BTreeMap<FontKey, FontMetrics>,
+  shapers: BTreeMap<FontKey, Arc<Shaper>>,
+  /// Weight and style of the faces, to synthesize the missing ones.
+  face_styles: BTreeMap<FontKey, (u16, FontStyle)>,
+  glyph_cache: GlyphCache<GlyphCacheKey>,
+  error_listeners: Vec<Sender<CanvasError>>,
 }
 
 impl <'a> Context2d<'a> {
-  fn read_pixels(&self, read_rect: Rect<i32>, canvas_size: Size2D<f64>) -> Vec<u8>{
-    let canvas_size = canvas_size.to_i32();
-    let canvas_rect = Rect::new(Point2D::new(0i32, 0i32), canvas_size);
-    let src_read_rect = canvas_rect.intersection(&read_rect).unwrap_or(Rect::zero());
+  /// Pixels of `read_rect` clipped to the current target.
+  fn read_pixels(&self, read_rect: Rect<i32>) -> Vec<u8>{
+    let data_surface = self.drawtarget.snapshot().get_data_surface();
+    let target_rect = Rect::new(Point2D::new(0i32, 0i32), data_surface.get_size());
+    let src_read_rect = target_rect.intersection(&read_rect).unwrap_or(Rect::zero());
 
     let mut image_data = vec![];
-    if src_read_rect.is_empty() || canvas_size.width <= 0 && canvas_size.height <= 0 {
+    if src_read_rect.is_empty() {
       return image_data;
     }
 
-    let data_surface = self.drawtarget.snapshot().get_data_surface();
     let mut src_data = Vec::new();
     data_surface.with_data(|element| { src_data = element.to_vec(); });
     let stride = data_surface.stride();
@@ -79,39 +127,58 @@ impl <'a> Context2d<'a> {
     let drawtarget = get_draw_target(size);
     let path_builder = drawtarget.create_path_builder();
 
-    let mut ctx = Context2d {
+    Context2d {
       state: PaintState::new(),
       saved_states: vec![],
+      size,
       drawtarget,
       path_builder,
-      font_context: RefCell::new(FontContext::new().expect("init FontContext fail")),
-      font_caches: BTreeMap::new(),
-    };
-    system_fonts::query_all().into_iter().for_each(|font| {
-      let font_property = system_fonts::FontPropertyBuilder::new().family(&font).build();
-      let (buffer, _) = system_fonts::get(&font_property).unwrap();
-      ctx.add_font_instance(buffer, font).unwrap();
-    });
-
-    ctx
+      path_bounds: Cell::new(None),
+      local_fonts: FontDatabase::new(),
+      font_keys: BTreeMap::new(),
+      font_metrics: BTreeMap::new(),
+      shapers: BTreeMap::new(),
+      face_styles: BTreeMap::new(),
+      glyph_cache: GlyphCache::new(DEFAULT_GLYPH_CACHE_LIMIT),
+      error_listeners: vec![],
+    }
   }
 
   pub fn start(size: Size2D<i32>) -> Sender<CanvasMsg> {
     let (sender, receiver) = channel::<CanvasMsg>();
     thread::Builder::new().name("CanvasThread".to_owned()).spawn(move || {
       let mut painter = Context2d::new(size);
-      loop {
-        let msg = receiver.recv();
-        match msg.expect("CanvasThread recive msg fail") {
+      // recv fails once every sender is dropped, nobody can talk to this canvas anymore
+      while let Ok(msg) = receiver.recv() {
+        match msg {
           CanvasMsg::Canvas2d(message) => {
-            painter.handle_canvas2d_msg(message);
+            if let Err(e) = painter.handle_canvas2d_msg(message) {
+              painter.report_error(e);
+            }
           },
           CanvasMsg::Close => break,
           CanvasMsg::FromScript(message) => {
             match message {
+              FromScriptMsg::RegisterFont(data, face, chan) => {
+                let result = painter.local_fonts.register_face(&face, Arc::new(data)).map(|_| ());
+                chan.send(result).ok();
+              },
+              FromScriptMsg::GlyphCacheStats(chan) => {
+                chan.send(painter.glyph_cache.stats()).ok();
+              },
+              FromScriptMsg::SetGlyphCacheLimit(limit) => painter.glyph_cache.set_limit(limit),
               FromScriptMsg::SendPixels(chan) => {
                 painter.send_pixels(chan)
-              }
+              },
+              FromScriptMsg::SubscribeErrors(chan) => {
+                painter.error_listeners.push(chan)
+              },
+              FromScriptMsg::ToBuffer(format, chan) => {
+                chan.send(painter.to_buffer(format)).ok();
+              },
+              FromScriptMsg::ToDataURL(format, chan) => {
+                chan.send(painter.to_data_url(format)).ok();
+              },
             }
           }
         }
@@ -121,27 +188,86 @@ impl <'a> Context2d<'a> {
     sender
   }
 
-  fn handle_canvas2d_msg(&mut self, message: Canvas2dMsg) {
+  fn report_error(&mut self, error: CanvasError) {
+    // drop listeners whose receiver is gone
+    self.error_listeners.retain(|listener| listener.send(error.clone()).is_ok());
+  }
+
+  fn handle_canvas2d_msg(&mut self, message: Canvas2dMsg) -> Result<(), CanvasError> {
     match message {
-      Canvas2dMsg::FillText(text, x, y, max_width) => self.fill_text(text, x, y, max_width),
-      Canvas2dMsg::StrokeText(text, x, y, max_width) => self.stroke_text(text, x, y, max_width),
-      Canvas2dMsg::FillRect(ref rect) => self.fill_rect(rect),
-      Canvas2dMsg::StrokeRect(ref rect) => self.stroke_rect(rect),
+      Canvas2dMsg::FillText(text, x, y, max_width) => return self.fill_text(text, x, y, max_width),
+      Canvas2dMsg::StrokeText(text, x, y, max_width) => return self.stroke_text(text, x, y, max_width),
+      Canvas2dMsg::MeasureText(text, chan) => {
+        let metrics = self.measure_text(&text);
+        chan.send(metrics.clone().unwrap_or_default()).ok();
+        return metrics.map(|_| ());
+      },
+      Canvas2dMsg::FillRect(ref rect) => self.draw_filtered(Some(*rect), |ctx| ctx.fill_rect(rect)),
+      Canvas2dMsg::StrokeRect(ref rect) => {
+        let bounds = self.stroke_bounds(Some(*rect));
+        self.draw_filtered(bounds, |ctx| ctx.stroke_rect(rect))
+      },
       Canvas2dMsg::ClearRect(ref rect) => self.clear_rect(rect),
       Canvas2dMsg::BeginPath => self.begin_path(),
       Canvas2dMsg::ClosePath => self.close_path(),
-      Canvas2dMsg::Fill => self.fill(),
-      Canvas2dMsg::Stroke => self.stroke(),
-      Canvas2dMsg::Clip => self.clip(),
+      Canvas2dMsg::Fill(fill_rule) => {
+        let bounds = self.path_bounds.get();
+        self.draw_filtered(bounds, |ctx| ctx.fill(fill_rule))
+      },
+      Canvas2dMsg::Stroke => {
+        let bounds = self.stroke_bounds(self.path_bounds.get());
+        self.draw_filtered(bounds, |ctx| ctx.stroke())
+      },
+      Canvas2dMsg::Clip(fill_rule) => self.clip(fill_rule),
       Canvas2dMsg::IsPointInPath(x, y, fill_rule, chan) => {
         self.is_point_in_path(x, y, fill_rule, chan)
       },
+      Canvas2dMsg::FillPath2D(ref path, fill_rule) => {
+        let (path, bounds) = self.build_path_with_bounds(path);
+        let path = apply_fill_rule(path, fill_rule);
+        self.draw_filtered(bounds, |ctx| ctx.fill_path(&path))
+      },
+      Canvas2dMsg::StrokePath2D(ref path) => {
+        let (path, bounds) = self.build_path_with_bounds(path);
+        let bounds = self.stroke_bounds(bounds);
+        self.draw_filtered(bounds, |ctx| ctx.stroke_path(&path))
+      },
+      Canvas2dMsg::ClipPath2D(ref path, fill_rule) => {
+        let path = apply_fill_rule(self.build_path(path), fill_rule);
+        self.drawtarget.push_clip(&path)
+      },
+      Canvas2dMsg::IsPointInPath2D(path, x, y, fill_rule, chan) => {
+        let path = apply_fill_rule(self.build_path(&path), fill_rule);
+        chan.send(path.contains_point(x, y, &self.state.transform)).ok();
+      },
+      Canvas2dMsg::IsPointInStroke(x, y, chan) => self.is_point_in_stroke(x, y, chan),
+      Canvas2dMsg::IsPointInStroke2D(path, x, y, chan) => {
+        let path = self.build_path(&path);
+        let result = path.stroke_contains_point(&self.state.stroke_options(), x, y, &self.state.transform);
+        chan.send(result).ok();
+      },
       Canvas2dMsg::DrawImage(imagedata, image_size, dest_rect, source_rect,
                               smoothing_enabled) => {
-        self.draw_image(imagedata, image_size, dest_rect, source_rect, smoothing_enabled)
+        check_coordinates("drawImage", &[image_size.width, image_size.height])?;
+        check_coordinates("drawImage", &rect_values(&dest_rect))?;
+        check_coordinates("drawImage", &rect_values(&source_rect))?;
+        check_image_data("drawImage", &imagedata, image_size)?;
+        if let Some((source_rect, dest_rect)) = clip_to_image(image_size, source_rect, dest_rect) {
+          self.draw_filtered(Some(dest_rect.to_f32()), |ctx| ctx.draw_image(imagedata, image_size, dest_rect, source_rect, smoothing_enabled))
+        }
       }
       Canvas2dMsg::DrawImageSelf(image_size, dest_rect, source_rect, smoothing_enabled) => {
-        self.draw_image_self(image_size, dest_rect, source_rect, smoothing_enabled)
+        check_coordinates("drawImage", &[image_size.width, image_size.height])?;
+        check_coordinates("drawImage", &rect_values(&dest_rect))?;
+        check_coordinates("drawImage", &rect_values(&source_rect))?;
+        // the canvas may be smaller than the size the client knows of
+        let image_size = Size2D::new(image_size.width.min(self.size.width as f64),
+                                     image_size.height.min(self.size.height as f64));
+        if let Some((source_rect, dest_rect)) = clip_to_image(image_size, source_rect, dest_rect) {
+          // read before a filter moves the drawing to an intermediate target
+          let image_data = self.read_pixels(source_rect.to_i32());
+          self.draw_filtered(Some(dest_rect.to_f32()), |ctx| ctx.draw_image_self(image_data, dest_rect, source_rect, smoothing_enabled))
+        }
       }
       Canvas2dMsg::MoveTo(ref point) => self.move_to(point),
       Canvas2dMsg::LineTo(ref point) => self.line_to(point),
@@ -164,41 +290,88 @@ impl <'a> Context2d<'a> {
       Canvas2dMsg::RestoreContext => self.restore_context_state(),
       Canvas2dMsg::SaveContext => self.save_context_state(),
       Canvas2dMsg::SetFillStyle(style) => self.set_fill_style(style),
+      Canvas2dMsg::SetFilter(filter) => self.set_filter(&filter),
       Canvas2dMsg::SetFontStyle(font_rule) => self.set_font_style(&font_rule),
       Canvas2dMsg::SetStrokeStyle(style) => self.set_stroke_style(style),
       Canvas2dMsg::SetLineWidth(width) => self.set_line_width(width),
       Canvas2dMsg::SetLineCap(cap) => self.set_line_cap(cap),
       Canvas2dMsg::SetLineJoin(join) => self.set_line_join(join),
       Canvas2dMsg::SetMiterLimit(limit) => self.set_miter_limit(limit),
+      Canvas2dMsg::SetLineDash(segments) => self.set_line_dash(segments),
+      Canvas2dMsg::SetLineDashOffset(offset) => self.set_line_dash_offset(offset),
+      Canvas2dMsg::GetLineDash(chan) => {
+        chan.send(self.state.line_dash.clone()).ok();
+      },
       Canvas2dMsg::SetTransform(ref matrix) => self.set_transform(matrix),
       Canvas2dMsg::SetGlobalAlpha(alpha) => self.set_global_alpha(alpha),
       Canvas2dMsg::SetGlobalComposition(op) => self.set_global_composition(op),
       Canvas2dMsg::GetImageData(dest_rect, canvas_size, chan)
-          => self.image_data(dest_rect, canvas_size, chan),
+          => return self.image_data(dest_rect, canvas_size, chan),
       Canvas2dMsg::PutImageData(imagedata, offset, image_data_size, dirty_rect)
-          => self.put_image_data(imagedata, offset, image_data_size, dirty_rect),
+          => return self.put_image_data(imagedata, offset, image_data_size, dirty_rect),
       Canvas2dMsg::SetShadowOffsetX(value) => self.set_shadow_offset_x(value),
       Canvas2dMsg::SetShadowOffsetY(value) => self.set_shadow_offset_y(value),
       Canvas2dMsg::SetShadowBlur(value) => self.set_shadow_blur(value),
       Canvas2dMsg::SetShadowColor(ref color) => self.set_shadow_color(color.to_azure_style()),
+      Canvas2dMsg::SetTextAlign(align) => self.state.text_align = align,
+      Canvas2dMsg::SetTextBaseline(baseline) => self.state.text_baseline = baseline,
+      Canvas2dMsg::SetDirection(direction) => self.state.direction = direction,
+      Canvas2dMsg::SetWritingMode(writing_mode) => self.state.writing_mode = writing_mode,
       Canvas2dMsg::NotImplement => { },
+    };
+    Ok(())
+  }
+
+  /// Looks a family up in the fonts of this canvas, then in the shared database.
+  /// With `c`, faces without a glyph for it are skipped.
+  fn query_face(&mut self, family: &str, c: Option<char>) -> Option<(FontScope, FaceId)> {
+    let font = &self.state.font;
+    let (weight, style, stretch) = (font.font_weight, font.font_style, font.font_stretch);
+    let covers = |db: &FontDatabase, id: FaceId| c.map_or(true, |c| db.face(id).has_glyph(c));
+    if let Some(id) = self.local_fonts.query(family, weight, style, stretch) {
+      if covers(&self.local_fonts, id) {
+        return Some((FontScope::Canvas, id));
+      }
+    }
+    load_shared_family(family);
+    let shared_fonts = shared_font_database();
+    match shared_fonts.query(family, weight, style, stretch) {
+      Some(id) if covers(&shared_fonts, id) => Some((FontScope::Shared, id)),
+      _ => None,
     }
   }
 
-  fn add_font_instance(&mut self, bytes: Vec<u8>, family_name: String) -> Result<(), ()> {
-    match self.font_caches.entry(family_name) {
-      Entry::Occupied(_) => Ok(()),
-      Entry::Vacant(entry) => {
-        let font_key = FontKey::new();
-        match self.font_context.borrow_mut().add_font_from_memory(&font_key, Arc::new(bytes), 0) {
-          Ok(_) => {
-            entry.insert(font_key);
-            Ok(())
-          },
-          Err(e) => panic!(e),
-        }
+  /// The css families first, then the configured fallbacks, then any face covering `c`.
+  fn query_face_for_char(&mut self, families: &[String], c: char) -> Option<(FontScope, FaceId)> {
+    for family in families {
+      if let Some(face) = self.query_face(family, Some(c)) {
+        return Some(face);
       }
     }
+    let family = shared_family_for_char(c)?;
+    self.query_face(&family, Some(c))
+  }
+
+  /// Registers a face in the font context the first time it is used.
+  fn font_key(&mut self, scope: FontScope, face_id: FaceId) -> Result<FontKey, CanvasError> {
+    if let Some(font_key) = self.font_keys.get(&(scope, face_id)) {
+      return Ok(*font_key);
+    }
+    // shared faces are parsed once for every canvas
+    let (face, loaded) = match scope {
+      FontScope::Canvas => (self.local_fonts.face(face_id).clone(), self.local_fonts.load_face(face_id)?),
+      FontScope::Shared => {
+        let mut shared_fonts = shared_font_database();
+        (shared_fonts.face(face_id).clone(), shared_fonts.load_face(face_id)?)
+      },
+    };
+
+    let font_key = loaded.font_key;
+    self.font_keys.insert((scope, face_id), font_key);
+    self.font_metrics.insert(font_key, face.metrics);
+    self.face_styles.insert(font_key, (face.weight, face.style));
+    self.shapers.insert(font_key, loaded.shaper);
+    Ok(font_key)
   }
 
   fn save_context_state(&mut self) {
@@ -213,78 +386,237 @@ impl <'a> Context2d<'a> {
     }
   }
 
-  fn fill_text(&mut self, text: String, x: f32, y: f32, max_width: Option<f32>) {
-    self.draw_text(text, x, y, max_width);
-    self.fill();
+  fn fill_text(&mut self, text: String, x: f32, y: f32, max_width: Option<f32>) -> Result<(), CanvasError> {
+    self.draw_text(text, x, y, max_width)?;
+    let bounds = self.path_bounds.get();
+    self.draw_filtered(bounds, |ctx| ctx.fill(FillRule::Nonzero));
+    Ok(())
   }
 
-  fn stroke_text(&mut self, text: String, x: f32, y: f32, max_width: Option<f32>) {
-    self.draw_text(text, x, y, max_width);
-    self.stroke();
+  fn stroke_text(&mut self, text: String, x: f32, y: f32, max_width: Option<f32>) -> Result<(), CanvasError> {
+    self.draw_text(text, x, y, max_width)?;
+    let bounds = self.stroke_bounds(self.path_bounds.get());
+    self.draw_filtered(bounds, |ctx| ctx.stroke());
+    Ok(())
   }
 
-  fn draw_text(&mut self, text: String, x: f32, y: f32, max_width: Option<f32>) {
+  fn has_glyph(&self, font_key: FontKey, c: char) -> bool {
+    shared_font_context().get_char_index(&font_key, c).is_some()
+  }
+
+  /// Splits `range` of `text` by the face covering it, marks stay with their base.
+  fn itemize_fonts(&mut self, text: &str, range: Range<usize>, primary_key: FontKey, families: &[String])
+                   -> Result<Vec<(FontKey, usize, usize)>, CanvasError> {
+    let mut font_runs: Vec<(FontKey, usize, usize)> = vec![];
+    for (i, c) in text[range.clone()].char_indices() {
+      let i = range.start + i;
+      let previous_key = font_runs.last().map(|&(font_key, _, _)| font_key);
+      let font_key = match previous_key {
+        Some(font_key) if is_cluster_extender(c) && self.has_glyph(font_key, c) => font_key,
+        _ if self.has_glyph(primary_key, c) => primary_key,
+        _ => match self.query_face_for_char(families, c) {
+          Some((scope, face_id)) => self.font_key(scope, face_id)?,
+          // nothing covers it, draw the .notdef glyph of the primary face
+          None => primary_key,
+        },
+      };
+      match font_runs.last_mut() {
+        Some(&mut (key, _, ref mut end)) if key == font_key => *end = i + c.len_utf8(),
+        _ => font_runs.push((font_key, i, i + c.len_utf8())),
+      }
+    }
+    Ok(font_runs)
+  }
+
+  // https://html.spec.whatwg.org/multipage/#text-preparation-algorithm
+  /// Lays `text` out on a single line, whitespaces are replaced by spaces.
+  fn layout_text(&mut self, text: &str) -> Result<TextLayout, CanvasError> {
+    let text = &text.replace(|c: char| c.is_ascii_whitespace(), " ");
+    let font_size = self.state.font.font_size;
+    let mut families = vec![self.state.font.font_family.clone()];
+    families.extend(self.state.font.fallback_families.iter().cloned());
+    families.extend(shared_font_database().fallback_families().iter().cloned());
+    families.push(SANS_SERIF_FONT_FAMILY.to_string());
+
+    let mut primary_face = None;
+    for family in &families {
+      primary_face = self.query_face(family, None);
+      if primary_face.is_some() {
+        break;
+      }
+    }
+    let (scope, face_id) = primary_face.ok_or_else(|| CanvasError::FontNotFound(self.state.font.font_family.clone()))?;
+    let primary_key = self.font_key(scope, face_id)?;
+    let metrics = self.font_metrics.get(&primary_key).cloned().unwrap_or_default().scale(font_size);
+
+    // vertical lines go down from the origin, `width` is their length
+    let vertical = self.state.writing_mode.is_vertical();
+    let mut runs = vec![];
+    let mut width = 0.0;
+    for (range, rtl) in visual_runs(text, self.state.direction == Direction::Rtl) {
+      let mut font_runs = self.itemize_fonts(text, range, primary_key, &families)?;
+      if rtl {
+        font_runs.reverse();
+      }
+      for (font_key, start, end) in font_runs {
+        let font_metrics = self.font_metrics.get(&font_key).cloned().unwrap_or_default();
+        let synthesis = self.synthesis(font_key);
+        let small_caps = self.state.font.font_variant == FontVariant::SmallCaps;
+        let synthetic_small_caps = small_caps && !self.shapers[&font_key].has_small_caps();
+        let segments = if vertical { orientation_runs(text, start..end) } else { vec![(start..end, false)] };
+        for (range, upright) in segments {
+          let mut pieces = if synthetic_small_caps {
+            small_caps_runs(&text[range])
+          } else {
+            vec![(text[range].to_string(), false)]
+          };
+          if rtl {
+            pieces.reverse();
+          }
+          for (text, scaled) in pieces {
+            let size = if scaled { font_size * SMALL_CAPS_SCALE } else { font_size };
+            let scale = size / font_metrics.units_per_em;
+            // rotated glyphs are centered on the line like upright ones
+            let center = (font_metrics.descent - font_metrics.ascent) * scale / 2.0;
+            let shaper = &self.shapers[&font_key];
+            let shaped = if upright {
+              shaper.shape_vertical(&text, small_caps && !synthetic_small_caps)
+            } else {
+              shaper.shape(&text, rtl, small_caps && !synthetic_small_caps)
+            };
+            let mut glyphs = vec![];
+            for glyph in shaped {
+              let (x_offset, y_offset) = (glyph.x_offset * scale, glyph.y_offset * scale);
+              let (x, y, advance) = match (vertical, upright) {
+                (false, _) => (width + x_offset, y_offset, glyph.x_advance),
+                (true, true) => (x_offset, y_offset - width, -glyph.y_advance),
+                (true, false) => (center + y_offset, -width - x_offset, glyph.x_advance),
+              };
+              glyphs.push(PositionedGlyph {
+                c: text[glyph.cluster as usize..].chars().next().unwrap_or(' '),
+                glyph_id: glyph.glyph_id,
+                x,
+                y,
+                rotated: vertical && !upright,
+              });
+              width += advance * scale + synthesis.extra_advance(size);
+            }
+            let size = Au::from_f32_px(size);
+            let instance = FontInstance::new(&font_key, size);
+            runs.push(TextRun { font_key, size, instance, synthesis, glyphs });
+          }
+        }
+      }
+    }
+    Ok(TextLayout { runs, metrics, width })
+  }
+
+  /// Bold and oblique are faked when the face lacks the weight or style of the font.
+  fn synthesis(&self, font_key: FontKey) -> Synthesis {
     let font = &self.state.font;
-    let family = &font.font_family;
-    let font_keys = &self.font_caches;
-    let size = &font.font_size;
-    let font_key = match font_keys.get(family) {
-      Some(f) => f,
-      None => font_keys.get(SANS_SERIF_FONT_FAMILY).expect("Get fallback font fail"),
+    let (face_weight, face_style) = self.face_styles.get(&font_key).cloned().unwrap_or((400, FontStyle::Normal));
+    Synthesis::new(font.font_weight, font.font_style, face_weight, face_style)
+  }
+
+  /// Outline of `glyph` with the synthetic styles of `run`, outlines are vectors
+  /// so one outline serves every position of the glyph.
+  fn cached_glyph(&mut self, run: &TextRun, glyph: &PositionedGlyph) -> Result<Rc<CachedGlyph>, CanvasError> {
+    let key = (run.font_key, run.size, glyph.glyph_id, run.synthesis);
+    self.glyph_cache.get_or_insert_with(key, || {
+      let glyph_key = GlyphKey::new(glyph.glyph_id, SubpixelOffset(0));
+      shared_font_context().glyph_outline(&run.instance, &glyph_key)
+        .map(|outline| CachedGlyph::new(run.synthesis.apply(outline.iter().collect(), run.size.to_f32_px())))
+        .map_err(|_| CanvasError::GlyphOutline(glyph.c))
+    })
+  }
+
+  fn measure_text(&mut self, text: &str) -> Result<TextMetrics, CanvasError> {
+    let layout = self.layout_text(text)?;
+    let metrics = &layout.metrics;
+    let align = text_align_offset(self.state.text_align, self.state.direction, layout.width);
+    // vertical lines are aligned along y and centered on x
+    let (origin_x, baseline) = if self.state.writing_mode.is_vertical() {
+      (0.0, align)
+    } else {
+      (align, text_baseline_offset(self.state.text_baseline, metrics))
     };
-    let instance = FontInstance::new(font_key, Au::from_px(* size as i32));
-    let mut offset_x = x;
-    let scale = match max_width {
-      Some(m) => {
-        let total_width = text.chars().map(|c| {
-          let font_context = self.font_context.borrow();
-          let pos = font_context.get_char_index(&font_key, c).expect("Get Char index font_context fail");
-          let glyph_key = GlyphKey::new(pos, SubpixelOffset(0));
-          let glyph_dimensions = font_context.glyph_dimensions(&instance, &glyph_key, false).expect("Get glyph dimensions fail");
-          glyph_dimensions.advance
-        }).sum::<f32>();
-        if total_width > m {
-          m / total_width
-        } else {
-          1.0
+
+    // glyph outlines are y up from the alphabetic baseline
+    let mut bounds: Option<Rect<f32>> = None;
+    for run in &layout.runs {
+      for glyph in &run.glyphs {
+        if let Some(glyph_bounds) = self.cached_glyph(run, glyph)?.bounds {
+          let glyph_bounds = if glyph.rotated { rotate_rect(&glyph_bounds) } else { glyph_bounds };
+          let glyph_bounds = glyph_bounds.translate(&Vector2D::new(glyph.x, glyph.y));
+          bounds = Some(bounds.map_or(glyph_bounds, |b| b.union(&glyph_bounds)));
         }
-      },
-      None => 1.0,
+      }
+    }
+    let bounds = bounds.unwrap_or(Rect::zero());
+    let (left, right) = (bounds.min_x(), bounds.max_x());
+    let (bottom, top) = (bounds.min_y(), bounds.max_y());
+
+    let em_ascent = metrics.units_per_em * metrics.ascent / (metrics.ascent + metrics.descent);
+    Ok(TextMetrics {
+      width: layout.width,
+      actual_bounding_box_left: -(origin_x + left),
+      actual_bounding_box_right: origin_x + right,
+      actual_bounding_box_ascent: top - baseline,
+      actual_bounding_box_descent: baseline - bottom,
+      font_bounding_box_ascent: metrics.ascent - baseline,
+      font_bounding_box_descent: metrics.descent + baseline,
+      em_height_ascent: em_ascent - baseline,
+      em_height_descent: metrics.units_per_em - em_ascent + baseline,
+      hanging_baseline: metrics.ascent * 0.8 - baseline,
+      alphabetic_baseline: -baseline,
+      ideographic_baseline: -metrics.descent - baseline,
+    })
+  }
+
+  fn draw_text(&mut self, text: String, x: f32, y: f32, max_width: Option<f32>) -> Result<(), CanvasError> {
+    let TextLayout { runs, metrics, width: total_width } = self.layout_text(&text)?;
+
+    // only horizontal text is compressed to `max_width`
+    let vertical = self.state.writing_mode.is_vertical();
+    let scale = match max_width {
+      Some(m) if !vertical && total_width > m => m / total_width,
+      _ => 1.0,
     };
-    text.chars().for_each(|c| {
-      let font_context = self.font_context.borrow();
-      let pos = font_context.get_char_index(&font_key, c).expect("Get Char index font_context fail");
-      let glyph_key = GlyphKey::new(pos, SubpixelOffset(0));
-      let glyph_dimensions = font_context.glyph_dimensions(&instance, &glyph_key, false).expect("Get glyph dimensions fail");
-      let text_width = glyph_dimensions.size.width;
-      let advance = glyph_dimensions.advance;
-      let advance_offset = advance / 2.0 * scale;
-      offset_x = offset_x + advance_offset;
-      let text_width = text_width as f32 * scale;
-      let glyph_outline = font_context.glyph_outline(&instance, &glyph_key).expect("Glyph outline fail");
-      glyph_outline.iter()
-        .map(|e| flip_text(scale)(e))
-        .for_each(|f| match f {
-          PathEvent::MoveTo(p) => self.move_to(
-            &Point2D::new(p.x + offset_x, p.y + y)
-          ),
-          PathEvent::LineTo(p) => self.line_to(&Point2D::new(p.x + offset_x, p.y + y)),
-          PathEvent::QuadraticTo(cp, ep) => self.quadratic_curve_to(
-            &Point2D::new(cp.x + offset_x, cp.y + y), &Point2D::new(ep.x + offset_x, ep.y + y)
-          ),
-          PathEvent::Close => self.close_path(),
-          PathEvent::CubicTo(cp1, cp2, ep) => self.bezier_curve_to(
-            &Point2D::new(cp1.x + offset_x, cp1.y + y),
-            &Point2D::new(cp2.x + offset_x, cp2.y + y),
-            &Point2D::new(ep.x + offset_x, ep.y + y)
-          ),
-          PathEvent::Arc(c, r, s, e) => self.arc(
-            &Point2D::new(c.x + offset_x, c.y + y),
-            r.angle_from_x_axis().get(), s.get(), e.get(), false
-          )
-        });
-      offset_x = offset_x + advance_offset + text_width;
-    });
+    let align = text_align_offset(self.state.text_align, self.state.direction, total_width * scale);
+    let (origin_x, y) = if vertical {
+      (x, y + align)
+    } else {
+      (x + align, y + text_baseline_offset(self.state.text_baseline, &metrics))
+    };
+
+    // a glyph failing mid-string must leave the current path untouched
+    let mut events = vec![];
+    for run in &runs {
+      for glyph in &run.glyphs {
+        let offset = Vector2D::new(origin_x + glyph.x * scale, y - glyph.y);
+        let rotated = glyph.rotated;
+        let cached_glyph = self.cached_glyph(run, glyph)?;
+        events.extend(cached_glyph.outline.iter().cloned()
+          .map(|e| if rotated { rotate_path_event(e) } else { e })
+          .map(|e| flip_text(scale)(e))
+          .map(|e| translate_path_event(e, offset)));
+      }
+    }
+    for event in events {
+      self.append_path_event(event);
+    }
+    Ok(())
+  }
+
+  fn append_path_event(&self, event: PathEvent) {
+    match event {
+      PathEvent::MoveTo(p) => self.move_to(&p),
+      PathEvent::LineTo(p) => self.line_to(&p),
+      PathEvent::QuadraticTo(cp, ep) => self.quadratic_curve_to(&cp, &ep),
+      PathEvent::CubicTo(cp1, cp2, ep) => self.bezier_curve_to(&cp1, &cp2, &ep),
+      PathEvent::Arc(c, r, s, e) => self.arc(&c, r.angle_from_x_axis().get(), s.get(), e.get(), false),
+      PathEvent::Close => self.close_path(),
+    }
   }
 
   fn fill_rect(&self, rect: &Rect<f32>) {
@@ -292,29 +624,18 @@ impl <'a> Context2d<'a> {
       return; // Paint nothing if gradient size is zero.
     }
 
-    let draw_rect = Rect::new(rect.origin,
-      match self.state.fill_style {
-        Pattern::Surface(ref surface) => {
-          let surface_size = surface.size();
-          match (surface.repeat_x, surface.repeat_y) {
-            (true, true) => rect.size,
-            (true, false) => Size2D::new(rect.size.width, surface_size.height as f32),
-            (false, true) => Size2D::new(surface_size.width as f32, rect.size.height),
-            (false, false) => Size2D::new(surface_size.width as f32, surface_size.height as f32),
-          }
-        },
-        _ => rect.size,
-      }
-    );
-
     if self.need_to_draw_shadow() {
-      self.draw_with_shadow(&draw_rect, |new_draw_target: &DrawTarget| {
-        new_draw_target.fill_rect(&draw_rect, self.state.fill_style.to_pattern_ref(),
-                                  Some(&self.state.draw_options));
+      self.draw_with_shadow(rect, |new_draw_target: &DrawTarget| {
+        self.with_pattern_area(new_draw_target, &self.state.fill_pattern_area, || {
+          new_draw_target.fill_rect(rect, self.state.fill_style.to_pattern_ref(),
+                                    Some(&self.state.draw_options));
+        });
       });
     } else {
-      self.drawtarget.fill_rect(&draw_rect, self.state.fill_style.to_pattern_ref(),
-                                Some(&self.state.draw_options));
+      self.with_pattern_area(&self.drawtarget, &self.state.fill_pattern_area, || {
+        self.drawtarget.fill_rect(rect, self.state.fill_style.to_pattern_ref(),
+                                  Some(&self.state.draw_options));
+      });
     }
   }
 
@@ -327,71 +648,168 @@ impl <'a> Context2d<'a> {
       return; // Paint nothing if gradient size is zero.
     }
 
-    if self.need_to_draw_shadow() {
-      self.draw_with_shadow(&rect, |new_draw_target: &DrawTarget| {
-        new_draw_target.stroke_rect(rect, self.state.stroke_style.to_pattern_ref(),
-                                    &self.state.stroke_opts, &self.state.draw_options);
-      });
-    } else if rect.size.width == 0. || rect.size.height == 0. {
-      let cap = match self.state.stroke_opts.line_join {
+    // stroked as a path, azure only takes a dash offset for paths
+    let path_builder = self.drawtarget.create_path_builder();
+    let mut stroke_opts = self.state.stroke_options();
+    path_builder.move_to(rect.origin);
+    if rect.size.width == 0. || rect.size.height == 0. {
+      stroke_opts.line_cap = match self.state.stroke_opts.line_join {
         JoinStyle::Round => CapStyle::Round,
         _ => CapStyle::Butt
       };
+      path_builder.line_to(rect.bottom_right());
+    } else {
+      path_builder.line_to(rect.top_right());
+      path_builder.line_to(rect.bottom_right());
+      path_builder.line_to(rect.bottom_left());
+      path_builder.close();
+    }
+    let path = path_builder.finish();
 
-      let stroke_opts =
-          StrokeOptions::new(self.state.stroke_opts.line_width,
-                              self.state.stroke_opts.line_join,
-                              cap,
-                              self.state.stroke_opts.miter_limit,
-                              self.state.stroke_opts.mDashPattern);
-      self.drawtarget.stroke_line(rect.origin, rect.bottom_right(),
-                                  self.state.stroke_style.to_pattern_ref(),
-                                  &stroke_opts, &self.state.draw_options);
+    if self.need_to_draw_shadow() {
+      self.draw_with_shadow(&rect, |new_draw_target: &DrawTarget| {
+        self.with_pattern_area(new_draw_target, &self.state.stroke_pattern_area, || {
+          self.stroke_on(new_draw_target, &path, &stroke_opts);
+        });
+      });
     } else {
-      self.drawtarget.stroke_rect(rect, self.state.stroke_style.to_pattern_ref(),
-                                  &self.state.stroke_opts, &self.state.draw_options);
+      self.with_pattern_area(&self.drawtarget, &self.state.stroke_pattern_area, || {
+        self.stroke_on(&self.drawtarget, &path, &stroke_opts);
+      });
     }
   }
 
+  fn stroke_on(&self, draw_target: &DrawTarget, path: &Path, stroke_opts: &StrokeOptions) {
+    stroke_with_dash_offset(draw_target, path, self.state.stroke_style.to_pattern_ref(),
+                            stroke_opts, self.state.line_dash_offset, &self.state.draw_options);
+  }
+
   fn begin_path(&mut self) {
-    self.path_builder = self.drawtarget.create_path_builder()
+    self.path_builder = self.drawtarget.create_path_builder();
+    self.path_bounds.set(None);
+  }
+
+  fn extend_path_bounds(&self, points: &[Point2D<f32>]) {
+    let bounds = points.iter().fold(self.path_bounds.get(), |bounds, p| Some(match bounds {
+      Some(b) => {
+        let min = Point2D::new(b.min_x().min(p.x), b.min_y().min(p.y));
+        let max = Point2D::new(b.max_x().max(p.x), b.max_y().max(p.y));
+        Rect::new(min, Size2D::new(max.x - min.x, max.y - min.y))
+      },
+      None => Rect::new(*p, Size2D::zero()),
+    }));
+    self.path_bounds.set(bounds);
+  }
+
+  /// `bounds` grown by the farthest a stroke reaches out of its path, miter joins
+  /// and square caps included.
+  fn stroke_bounds(&self, bounds: Option<Rect<f32>>) -> Option<Rect<f32>> {
+    let stroke_opts = &self.state.stroke_opts;
+    let extent = stroke_opts.line_width * stroke_opts.miter_limit.max(SQRT_2) / 2.0;
+    bounds.map(|bounds| bounds.inflate(extent, extent))
   }
 
   fn close_path(&self) {
     self.path_builder.close()
   }
 
-  fn fill(&self) {
+  fn fill(&self, fill_rule: FillRule) {
+    self.fill_path(&apply_fill_rule(self.path_builder.finish(), fill_rule))
+  }
+
+  fn fill_path(&self, path: &Path) {
     if is_zero_size_gradient(&self.state.fill_style) {
       return; // Paint nothing if gradient size is zero.
     }
 
-    self.drawtarget.fill(&self.path_builder.finish(),
-                          self.state.fill_style.to_pattern_ref(),
-                          &self.state.draw_options);
+    self.with_pattern_area(&self.drawtarget, &self.state.fill_pattern_area, || {
+      self.drawtarget.fill(path,
+                            self.state.fill_style.to_pattern_ref(),
+                            &self.state.draw_options);
+    });
   }
 
   fn stroke(&self) {
+    self.stroke_path(&self.path_builder.finish())
+  }
+
+  fn stroke_path(&self, path: &Path) {
     if is_zero_size_gradient(&self.state.stroke_style) {
       return; // Paint nothing if gradient size is zero.
     }
 
-    self.drawtarget.stroke(&self.path_builder.finish(),
-                            self.state.stroke_style.to_pattern_ref(),
-                            &self.state.stroke_opts,
-                            &self.state.draw_options);
+    self.with_pattern_area(&self.drawtarget, &self.state.stroke_pattern_area, || {
+      self.stroke_on(&self.drawtarget, path, &self.state.stroke_options());
+    });
   }
 
-  fn clip(&self) {
-    self.drawtarget.push_clip(&self.path_builder.finish());
+  /// Clips to the painted area of a surface pattern not repeated both ways,
+  /// azure would stretch its edge pixels over the rest of the shape. With a shadow
+  /// `draw_target` is the shadow layer, so the shadow is not clipped as well.
+  fn with_pattern_area<F: FnOnce()>(&self, draw_target: &DrawTarget, area: &Option<[Point2D<f32>; 4]>, draw: F) {
+    match *area {
+      Some(corners) => {
+        let path_builder = draw_target.create_path_builder();
+        path_builder.move_to(corners[0]);
+        for corner in &corners[1..] {
+          path_builder.line_to(*corner);
+        }
+        path_builder.close();
+        draw_target.push_clip(&path_builder.finish());
+        draw();
+        draw_target.pop_clip();
+      },
+      None => draw(),
+    }
+  }
+
+  fn build_path(&mut self, path: &Path2D) -> Path {
+    self.build_path_with_bounds(path).0
+  }
+
+  /// Builds a `Path2D` with a fresh path builder, the current path is left untouched.
+  fn build_path_with_bounds(&mut self, path: &Path2D) -> (Path, Option<Rect<f32>>) {
+    let current_path_builder = mem::replace(&mut self.path_builder, self.drawtarget.create_path_builder());
+    let current_path_bounds = self.path_bounds.replace(None);
+    for op in path.ops() {
+      match *op {
+        PathOp::MoveTo(ref point) => self.move_to(point),
+        PathOp::LineTo(ref point) => self.line_to(point),
+        PathOp::QuadraticCurveTo(ref cp, ref pt) => self.quadratic_curve_to(cp, pt),
+        PathOp::BezierCurveTo(ref cp1, ref cp2, ref pt) => self.bezier_curve_to(cp1, cp2, pt),
+        PathOp::Arc(ref center, radius, start, end, ccw) => self.arc(center, radius, start, end, ccw),
+        PathOp::ArcTo(ref cp1, ref cp2, radius) => self.arc_to(cp1, cp2, radius),
+        PathOp::Ellipse(ref center, radius_x, radius_y, rotation, start, end, ccw) => {
+          self.ellipse(center, radius_x, radius_y, rotation, start, end, ccw)
+        },
+        PathOp::Rect(ref rect) => self.rect(rect),
+        PathOp::ClosePath => self.close_path(),
+      }
+    }
+    let built = self.path_builder.finish();
+    self.path_builder = current_path_builder;
+    (built, self.path_bounds.replace(current_path_bounds))
+  }
+
+  fn clip(&self, fill_rule: FillRule) {
+    self.drawtarget.push_clip(&apply_fill_rule(self.path_builder.finish(), fill_rule));
   }
 
   fn is_point_in_path(&mut self, x: f64, y: f64,
-                      _fill_rule: FillRule, chan: Sender<bool>) {
+                      fill_rule: FillRule, chan: Sender<bool>) {
     let path = self.path_builder.finish();
-    let result = path.contains_point(x, y, &self.state.transform);
     self.path_builder = path.copy_to_builder();
-    chan.send(result).unwrap();
+    let result = apply_fill_rule(path, fill_rule).contains_point(x, y, &self.state.transform);
+    // the caller may have stopped waiting, which is not a failure of the canvas
+    chan.send(result).ok();
+  }
+
+  // https://html.spec.whatwg.org/multipage/#dom-context-2d-ispointinstroke
+  fn is_point_in_stroke(&mut self, x: f64, y: f64, chan: Sender<bool>) {
+    let path = self.path_builder.finish();
+    let result = path.stroke_contains_point(&self.state.stroke_options(), x, y, &self.state.transform);
+    self.path_builder = path.copy_to_builder();
+    chan.send(result).ok();
   }
 
   fn draw_image(&self, image_data: Vec<u8>, image_size: Size2D<f64>,
@@ -436,22 +854,73 @@ impl <'a> Context2d<'a> {
           start_angle: AzFloat,
           end_angle: AzFloat,
           ccw: bool) {
+    let radius = radius_x.max(radius_y);
+    self.extend_path_bounds(&[*center - Vector2D::new(radius, radius), *center + Vector2D::new(radius, radius)]);
     self.path_builder.ellipse(*center, radius_x, radius_y, rotation_angle, start_angle, end_angle, ccw);
   }
 
   fn set_fill_style(&mut self, style: FillOrStrokeStyle) {
-    if let Some(pattern) = style.to_azure_pattern(&self.drawtarget) {
-      self.state.fill_style = pattern
+    if let FillOrStrokeStyle::ConicGradient(style) = style {
+      // drawn by the next paint operation, for the transform it uses
+      self.state.fill_conic_gradient = Some((style, None));
+      self.state.fill_pattern_area = None;
+    } else if let Some(pattern) = style.to_azure_pattern(&self.drawtarget) {
+      self.state.fill_style = pattern;
+      self.state.fill_pattern_area = match style {
+        FillOrStrokeStyle::Surface(ref surface_style) => surface_style.painted_area(),
+        _ => None,
+      };
+      self.state.fill_conic_gradient = None;
+    }
+  }
+
+  fn set_filter(&mut self, filter: &str) {
+    if let Ok(filter) = CanvasFilter::new(filter) {
+      self.state.filter = filter;
     }
   }
 
   fn set_font_style(&mut self, font_style: &str) {
-    self.state.font = Font::new(font_style);
+    // invalid values are ignored like the other setters
+    if let Ok(font) = Font::new(font_style) {
+      self.state.font = font;
+    }
   }
 
   fn set_stroke_style(&mut self, style: FillOrStrokeStyle) {
-    if let Some(pattern) = style.to_azure_pattern(&self.drawtarget) {
-      self.state.stroke_style = pattern
+    if let FillOrStrokeStyle::ConicGradient(style) = style {
+      self.state.stroke_conic_gradient = Some((style, None));
+      self.state.stroke_pattern_area = None;
+    } else if let Some(pattern) = style.to_azure_pattern(&self.drawtarget) {
+      self.state.stroke_style = pattern;
+      self.state.stroke_pattern_area = match style {
+        FillOrStrokeStyle::Surface(ref surface_style) => surface_style.painted_area(),
+        _ => None,
+      };
+      self.state.stroke_conic_gradient = None;
+    }
+  }
+
+  /// Conic gradient patterns only fit the transform they were drawn for.
+  /// Called before painting, a pattern is only drawn again when the transform
+  /// changed since its last paint.
+  fn update_conic_gradients(&mut self) {
+    let drawtarget = &self.drawtarget;
+    let transform = self.state.transform;
+    let to_pattern = |gradient: &mut Option<(ConicGradientStyle, Option<Transform2D<f32>>)>| {
+      match *gradient {
+        Some((ref style, ref mut drawn_for)) if *drawn_for != Some(transform) => {
+          *drawn_for = Some(transform);
+          FillOrStrokeStyle::ConicGradient(style.clone()).to_azure_pattern(drawtarget)
+        },
+        _ => None,
+      }
+    };
+    if let Some(pattern) = to_pattern(&mut self.state.fill_conic_gradient) {
+      self.state.fill_style = pattern;
+    }
+    if let Some(pattern) = to_pattern(&mut self.state.stroke_conic_gradient) {
+      self.state.stroke_style = pattern;
     }
   }
 
@@ -471,9 +940,17 @@ impl <'a> Context2d<'a> {
     self.state.stroke_opts.miter_limit = limit;
   }
 
+  fn set_line_dash(&mut self, segments: Vec<f32>) {
+    self.state.set_line_dash(&segments);
+  }
+
+  fn set_line_dash_offset(&mut self, offset: f32) {
+    self.state.set_line_dash_offset(offset);
+  }
+
   fn set_transform(&mut self, transform: &Transform2D<f32>) {
     self.state.transform = transform.clone();
-    self.drawtarget.set_transform(transform)
+    self.drawtarget.set_transform(transform);
   }
 
   fn set_global_alpha(&mut self, alpha: f32) {
@@ -524,13 +1001,86 @@ impl <'a> Context2d<'a> {
                                             self.state.draw_options.composition);
   }
 
-  fn draw_image_self(&self, image_size: Size2D<f64>,
+  // https://html.spec.whatwg.org/multipage/#drawing-model
+  /// Runs `draw` on a transparent layer when there is a filter, like `draw_with_shadow`
+  /// does for shadows. The layer covers the device space `bounds` of the user space
+  /// `bounds` grown by the filter extent, or the whole canvas when they are unknown.
+  /// The filtered pixels then get the global alpha, the shadow and the composition
+  /// of the state, clips apply as they stay on the canvas target.
+  fn draw_filtered<R, F: FnOnce(&mut Self) -> R>(&mut self, bounds: Option<Rect<f32>>, draw: F) -> R {
+    self.update_conic_gradients();
+    if self.state.filter.is_none() {
+      return draw(self);
+    }
+    let canvas_rect = Rect::new(Point2D::zero(), self.size);
+    let extent = filter_extent(&self.state.filter);
+    let layer_rect = match bounds {
+      Some(bounds) => {
+        let device_bounds = self.state.transform.transform_rect(&bounds).inflate(extent, extent);
+        device_bounds.intersection(&canvas_rect.to_f32()).map(|rect| rect.round_out().to_i32())
+      },
+      None => Some(canvas_rect),
+    };
+    // off the canvas the draw still runs, on a layer that is not composited
+    let layer_size = layer_rect.map_or(Size2D::new(1, 1), |rect| rect.size);
+    let layer_origin = layer_rect.map_or(Point2D::zero(), |rect| rect.origin);
+    let layer = self.drawtarget.create_similar_draw_target(&layer_size, self.drawtarget.get_format());
+    let matrix = Transform2D::identity()
+      .pre_translate(Vector2D::new(-layer_origin.x as f32, -layer_origin.y as f32))
+      .pre_mul(&self.state.transform);
+    layer.set_transform(&matrix);
+    let state = self.state.clone();
+    self.state.draw_options = DrawOptions::new(1.0, CompositionOp::Over, AntialiasMode::Default);
+    self.state.shadow_color = Color::transparent();
+    let canvas_target = mem::replace(&mut self.drawtarget, layer);
+    let result = draw(self);
+
+    let mut pixels = self.read_pixels(Rect::new(Point2D::zero(), layer_size));
+    self.drawtarget = canvas_target;
+    self.state = state;
+    if layer_rect.is_none() {
+      return result;
+    }
+    let mut filter = self.state.filter.clone();
+    // the shadow is cast by the image with the global alpha
+    filter.functions.push(FilterFunction::Opacity(self.state.draw_options.alpha));
+    apply_filter(&filter, &mut pixels, layer_size);
+
+    let surface = match self.drawtarget.create_source_surface_from_data(&mut pixels,
+                                                                         layer_size,
+                                                                         layer_size.width * 4,
+                                                                         SurfaceFormat::B8G8R8A8) {
+      Some(surface) => surface,
+      None => return result,
+    };
+    let origin = Point2D::new(layer_origin.x as AzFloat, layer_origin.y as AzFloat);
+    if self.need_to_draw_shadow() {
+      // shadows are drawn in device space
+      self.drawtarget.draw_surface_with_shadow(surface,
+                                               &origin,
+                                               &self.state.shadow_color,
+                                               &Vector2D::new(self.state.shadow_offset_x as AzFloat,
+                                                              self.state.shadow_offset_y as AzFloat),
+                                               (self.state.shadow_blur / 2.0f64) as AzFloat,
+                                               self.state.draw_options.composition);
+    } else {
+      let size = Size2D::new(layer_size.width as AzFloat, layer_size.height as AzFloat);
+      self.drawtarget.set_transform(&Transform2D::identity());
+      self.drawtarget.draw_surface(surface,
+                                   Rect::new(origin, size),
+                                   Rect::new(Point2D::zero(), size),
+                                   DrawSurfaceOptions::new(Filter::Point, true),
+                                   DrawOptions::new(1.0, self.state.draw_options.composition, AntialiasMode::Default));
+      self.drawtarget.set_transform(&self.state.transform);
+    }
+    result
+  }
+
+
+  /// `image_data` are the pixels of `source_rect` read from this canvas.
+  fn draw_image_self(&self, image_data: Vec<u8>,
                       dest_rect: Rect<f64>, source_rect: Rect<f64>,
                       smoothing_enabled: bool) {
-    // Reads pixels from source image
-    // In this case source and target are the same canvas
-    let image_data = self.read_pixels(source_rect.to_i32(), image_size);
-
     if self.need_to_draw_shadow() {
       let rect = Rect::new(Point2D::new(dest_rect.origin.x as f32, dest_rect.origin.y as f32),
                             Size2D::new(dest_rect.size.width as f32, dest_rect.size.height as f32));
@@ -542,21 +1092,24 @@ impl <'a> Context2d<'a> {
       });
     } else {
       // Writes on target canvas
-      write_image(&self.drawtarget, image_data, image_size, dest_rect,
+      write_image(&self.drawtarget, image_data, source_rect.size, dest_rect,
                   smoothing_enabled, self.state.draw_options.composition,
                   self.state.draw_options.alpha);
     }
   }
 
   fn move_to(&self, point: &Point2D<AzFloat>) {
+    self.extend_path_bounds(&[*point]);
     self.path_builder.move_to(*point)
   }
 
   fn line_to(&self, point: &Point2D<AzFloat>) {
+    self.extend_path_bounds(&[*point]);
     self.path_builder.line_to(*point)
   }
 
   fn rect(&self, rect: &Rect<f32>) {
+    self.extend_path_bounds(&[rect.origin, rect.bottom_right()]);
     self.path_builder.move_to(Point2D::new(rect.origin.x, rect.origin.y));
     self.path_builder.line_to(Point2D::new(rect.origin.x + rect.size.width, rect.origin.y));
     self.path_builder.line_to(Point2D::new(rect.origin.x + rect.size.width,
@@ -568,6 +1121,7 @@ impl <'a> Context2d<'a> {
   fn quadratic_curve_to(&self,
                           cp: &Point2D<AzFloat>,
                           endpoint: &Point2D<AzFloat>) {
+    self.extend_path_bounds(&[*cp, *endpoint]);
     self.path_builder.quadratic_curve_to(cp, endpoint)
   }
 
@@ -575,6 +1129,7 @@ impl <'a> Context2d<'a> {
                         cp1: &Point2D<AzFloat>,
                         cp2: &Point2D<AzFloat>,
                         endpoint: &Point2D<AzFloat>) {
+    self.extend_path_bounds(&[*cp1, *cp2, *endpoint]);
     self.path_builder.bezier_curve_to(cp1, cp2, endpoint)
   }
 
@@ -584,6 +1139,7 @@ impl <'a> Context2d<'a> {
             start_angle: AzFloat,
             end_angle: AzFloat,
             ccw: bool) {
+    self.extend_path_bounds(&[*center - Vector2D::new(radius, radius), *center + Vector2D::new(radius, radius)]);
     self.path_builder.arc(*center, radius, start_angle, end_angle, ccw)
   }
 
@@ -592,53 +1148,10 @@ impl <'a> Context2d<'a> {
                 cp2: &Point2D<AzFloat>,
                 radius: AzFloat) {
     let cp0 = self.path_builder.get_current_point();
-    let cp1 = *cp1;
-    let cp2 = *cp2;
-
-    if (cp0.x == cp1.x && cp0.y == cp1.y) || cp1 == cp2 || radius == 0.0 {
-      self.line_to(&cp1);
-      return;
-    }
-
-    // if all three control points lie on a single straight line,
-    // connect the first two by a straight line
-    let direction = (cp2.x - cp1.x) * (cp0.y - cp1.y) + (cp2.y - cp1.y) * (cp1.x - cp0.x);
-    if direction == 0.0 {
-      self.line_to(&cp1);
-      return;
-    }
-
-    // otherwise, draw the Arc
-    let a2 = (cp0.x - cp1.x).powi(2) + (cp0.y - cp1.y).powi(2);
-    let b2 = (cp1.x - cp2.x).powi(2) + (cp1.y - cp2.y).powi(2);
-    let d = {
-      let c2 = (cp0.x - cp2.x).powi(2) + (cp0.y - cp2.y).powi(2);
-      let cosx = (a2 + b2 - c2) / (2.0 * (a2 * b2).sqrt());
-      let sinx = (1.0 - cosx.powi(2)).sqrt();
-      radius / ((1.0 - cosx) / sinx)
-    };
-
-    // first tangent point
-    let anx = (cp1.x - cp0.x) / a2.sqrt();
-    let any = (cp1.y - cp0.y) / a2.sqrt();
-    let tp1 = Point2D::new(cp1.x - anx * d, cp1.y - any * d);
-
-    // second tangent point
-    let bnx = (cp1.x - cp2.x) / b2.sqrt();
-    let bny = (cp1.y - cp2.y) / b2.sqrt();
-    let tp2 = Point2D::new(cp1.x - bnx * d, cp1.y - bny * d);
-
-    // arc center and angles
-    let anticlockwise = direction < 0.0;
-    let cx = tp1.x + any * radius * if anticlockwise { 1.0 } else { -1.0 };
-    let cy = tp1.y - anx * radius * if anticlockwise { 1.0 } else { -1.0 };
-    let angle_start = (tp1.y - cy).atan2(tp1.x - cx);
-    let angle_end = (tp2.y - cy).atan2(tp2.x - cx);
-
-    self.line_to(&tp1);
-    if [cx, cy, angle_start, angle_end].iter().all(|x| x.is_finite()) {
-      self.arc(&Point2D::new(cx, cy), radius,
-                angle_start, angle_end, anticlockwise);
+    let (tangent_point, arc) = arc_to_segment(&cp0, cp1, cp2, radius);
+    self.line_to(&tangent_point);
+    if let Some(ArcSegment { center, radius, start_angle, end_angle, anticlockwise }) = arc {
+      self.arc(&center, radius, start_angle, end_angle, anticlockwise);
     }
   }
 
@@ -646,12 +1159,13 @@ impl <'a> Context2d<'a> {
   fn put_image_data(&mut self, imagedata: Vec<u8>,
                     offset: Vector2D<f64>,
                     image_data_size: Size2D<f64>,
-                    mut dirty_rect: Rect<f64>) {
+                    mut dirty_rect: Rect<f64>) -> Result<(), CanvasError> {
+    check_coordinates("putImageData", &[offset.x, offset.y, image_data_size.width, image_data_size.height])?;
+    check_coordinates("putImageData", &rect_values(&dirty_rect))?;
     if image_data_size.width <= 0.0 || image_data_size.height <= 0.0 {
-      return
+      return Ok(())
     }
-
-    assert_eq!(image_data_size.width * image_data_size.height * 4.0, imagedata.len() as f64);
+    check_image_data("putImageData", &imagedata, image_data_size)?;
 
     // Step 1. TODO (neutered data)
 
@@ -666,6 +1180,9 @@ impl <'a> Context2d<'a> {
       dirty_rect.size.height = -dirty_rect.size.height;
     }
 
+    // whole pixels, so the steps below keep the rect inside the image
+    dirty_rect = dirty_rect.round_out();
+
     // Step 3.
     if dirty_rect.origin.x < 0.0f64 {
       dirty_rect.size.width += dirty_rect.origin.x;
@@ -689,16 +1206,16 @@ impl <'a> Context2d<'a> {
     // 5) If either dirtyWidth or dirtyHeight is negative or zero,
     // stop without affecting any bitmaps
     if dirty_rect.size.width <= 0.0 || dirty_rect.size.height <= 0.0 {
-      return
+      return Ok(())
     }
 
     // Step 6.
-    let dest_rect = dirty_rect.translate(&offset).to_i32();
+    let first_pixel = dirty_rect.origin.to_i32();
+    let dest_rect = dirty_rect.to_i32().translate(&offset.to_i32());
 
     // azure_hl operates with integers. We need to cast the image size
     let image_size = image_data_size.to_i32();
 
-    let first_pixel = dest_rect.origin - offset.to_i32();
     let mut src_line = (first_pixel.y * (image_size.width * 4) + first_pixel.x * 4) as usize;
 
     let mut dest =
@@ -728,23 +1245,144 @@ impl <'a> Context2d<'a> {
                                     Rect::new(Point2D::new(0, 0), dest_rect.size),
                                     dest_rect.origin);
     }
-  }
-
-  fn image_data(&self, dest_rect: Rect<i32>, canvas_size: Size2D<f64>, chan: Sender<Vec<u8>>) {
-    let mut dest_data = self.read_pixels(dest_rect, canvas_size);
+    Ok(())
+  }
+
+  fn image_data(&self, dest_rect: Rect<i32>, canvas_size: Size2D<f64>,
+                chan: Sender<Vec<u8>>) -> Result<(), CanvasError> {
+    let rect = Rect::new(Point2D::new(dest_rect.origin.x as f64, dest_rect.origin.y as f64),
+                         Size2D::new(dest_rect.size.width as f64, dest_rect.size.height as f64));
+    let checked = check_coordinates("getImageData", &[canvas_size.width, canvas_size.height])
+      .and_then(|_| check_coordinates("getImageData", &rect_values(&rect)));
+    if let Err(error) = checked {
+      // the caller still waits for an answer
+      chan.send(vec![]).ok();
+      return Err(error);
+    }
+    let canvas_rect = Rect::new(Point2D::zero(), canvas_size.to_i32());
+    let read_rect = canvas_rect.intersection(&dest_rect).unwrap_or(Rect::zero());
+    let mut dest_data = self.read_pixels(read_rect);
 
     // bgra -> rgba
     byte_swap(&mut dest_data);
-    chan.send(dest_data).expect("Send image_data fail");
+    chan.send(dest_data).ok();
+    Ok(())
+  }
+
+  fn canvas_pixels(&self) -> Vec<u8> {
+    let mut pixels = self.read_pixels(Rect::new(Point2D::zero(), self.size));
+    // bgra -> rgba
+    byte_swap(&mut pixels);
+    pixels
+  }
+
+  fn to_buffer(&self, format: ImageFormat) -> Result<Vec<u8>, CanvasError> {
+    encode(&self.canvas_pixels(), self.size.width as u32, self.size.height as u32, format)
+  }
+
+  fn to_data_url(&self, format: ImageFormat) -> Result<String, CanvasError> {
+    to_data_url(&self.canvas_pixels(), self.size.width as u32, self.size.height as u32, format)
   }
 
   fn send_pixels(&mut self, chan: Sender<Option<Vec<u8>>>) {
     self.drawtarget.snapshot().get_data_surface().with_data(|element| {
-      chan.send(Some(element.into())).expect("Send pixels fail");
+      chan.send(Some(element.into())).ok();
     })
   }
 }
 
+/// Path builders always produce nonzero winding paths, evenodd needs a copy of the path.
+fn apply_fill_rule(path: Path, fill_rule: FillRule) -> Path {
+  match fill_rule {
+    FillRule::Nonzero => path,
+    FillRule::Evenodd => path.copy_to_builder_with_fill_rule(fill_rule.to_azure_style()).finish(),
+  }
+}
+
+/// Combining marks, joiners and variation selectors, kept in the run of their base
+/// char so the shaper sees whole clusters.
+fn is_cluster_extender(c: char) -> bool {
+  match c as u32 {
+    0x0300...0x036f | 0x0483...0x0489 | 0x0591...0x05bd | 0x0610...0x061a | 0x064b...0x065f |
+    0x0900...0x0903 | 0x093a...0x094f | 0x0e31 | 0x0e34...0x0e3a | 0x0e47...0x0e4e |
+    0x1ab0...0x1aff | 0x1dc0...0x1dff | 0x200c | 0x200d | 0x20d0...0x20ff |
+    0xfe00...0xfe0f | 0xfe20...0xfe2f | 0x1f3fb...0x1f3ff | 0xe0100...0xe01ef => true,
+    _ => false,
+  }
+}
+
+/// Synthetic small caps, lowercase letters are turned into capitals to be drawn smaller.
+/// Each run is its text and whether it has to be scaled.
+fn small_caps_runs(text: &str) -> Vec<(String, bool)> {
+  let mut runs: Vec<(String, bool)> = vec![];
+  for c in text.chars() {
+    let scaled = match runs.last() {
+      Some(&(_, scaled)) if is_cluster_extender(c) => scaled,
+      _ => c.is_lowercase(),
+    };
+    match runs.last_mut() {
+      Some(&mut (ref mut run, run_scaled)) if run_scaled == scaled => run.extend(c.to_uppercase()),
+      _ => runs.push((c.to_uppercase().collect(), scaled)),
+    }
+  }
+  runs
+}
+
+/// Splits `range` of vertical `text` into upright and rotated runs, marks stay with their base.
+fn orientation_runs(text: &str, range: Range<usize>) -> Vec<(Range<usize>, bool)> {
+  let mut runs: Vec<(Range<usize>, bool)> = vec![];
+  for (i, c) in text[range.clone()].char_indices() {
+    let i = range.start + i;
+    let upright = match runs.last() {
+      Some(&(_, upright)) if is_cluster_extender(c) => upright,
+      _ => is_upright(c),
+    };
+    match runs.last_mut() {
+      Some(&mut (ref mut run, run_upright)) if run_upright == upright => run.end = i + c.len_utf8(),
+      _ => runs.push((i..i + c.len_utf8(), upright)),
+    }
+  }
+  runs
+}
+
+fn translate_path_event(event: PathEvent, v: Vector2D<f32>) -> PathEvent {
+  map_points(event, |p| p + v)
+}
+
+/// Turns a y up outline clockwise, its baseline becomes vertical.
+fn rotate_path_event(event: PathEvent) -> PathEvent {
+  map_points(event, |p| Point2D::new(p.y, -p.x))
+}
+
+fn rotate_rect(rect: &Rect<f32>) -> Rect<f32> {
+  Rect::new(Point2D::new(rect.min_y(), -rect.max_x()), Size2D::new(rect.size.height, rect.size.width))
+}
+
+// https://html.spec.whatwg.org/multipage/#text-preparation-algorithm
+/// Horizontal shift from the anchor point to the left edge of a text of `width`.
+fn text_align_offset(align: TextAlign, direction: Direction, width: f32) -> f32 {
+  let rtl = direction == Direction::Rtl;
+  match align {
+    TextAlign::Left => 0.0,
+    TextAlign::Right => -width,
+    TextAlign::Center => -width / 2.0,
+    TextAlign::Start => if rtl { -width } else { 0.0 },
+    TextAlign::End => if rtl { 0.0 } else { -width },
+  }
+}
+
+/// Vertical shift from the anchor point to the alphabetic baseline,
+/// the hanging baseline is placed at 80% of the ascent like browsers do.
+fn text_baseline_offset(baseline: TextBaseline, metrics: &FontMetrics) -> f32 {
+  match baseline {
+    TextBaseline::Top => metrics.ascent,
+    TextBaseline::Hanging => metrics.ascent * 0.8,
+    TextBaseline::Middle => (metrics.ascent - metrics.descent) / 2.0,
+    TextBaseline::Alphabetic => 0.0,
+    TextBaseline::Ideographic | TextBaseline::Bottom => -metrics.descent,
+  }
+}
+
 fn is_zero_size_gradient(pattern: &Pattern) -> bool {
   if let &Pattern::LinearGradient(ref gradient) = pattern {
     if gradient.is_zero_size() {
@@ -798,6 +1436,62 @@ impl RectToi32 for Rect<f64> {
 
 }
 
+/// Largest coordinate taken from clients, two of them still add up within the
+/// i32 range of azure.
+const MAX_COORDINATE: f64 = 1e9;
+
+/// Rejects non-finite values and values past `MAX_COORDINATE`, which would
+/// overflow once turned into pixels.
+fn check_coordinates(method: &str, values: &[f64]) -> Result<(), CanvasError> {
+  if values.iter().all(|value| value.is_finite() && value.abs() <= MAX_COORDINATE) {
+    Ok(())
+  } else {
+    Err(CanvasError::InvalidArgument(format!("{} takes finite coordinates up to {}", method, MAX_COORDINATE)))
+  }
+}
+
+fn rect_values(rect: &Rect<f64>) -> [f64; 4] {
+  [rect.origin.x, rect.origin.y, rect.size.width, rect.size.height]
+}
+
+/// Checks `image_data` holds the RGBA pixels of a whole image of `image_size`.
+fn check_image_data(method: &str, image_data: &[u8], image_size: Size2D<f64>) -> Result<(), CanvasError> {
+  let (width, height) = (image_size.width, image_size.height);
+  if width < 0.0 || height < 0.0 || width.fract() != 0.0 || height.fract() != 0.0 {
+    return Err(CanvasError::InvalidArgument(format!("{} takes an image size in whole pixels", method)));
+  }
+  let expected = (width * height * 4.0) as usize;
+  if expected != image_data.len() {
+    return Err(CanvasError::InvalidImageData { expected, actual: image_data.len() });
+  }
+  Ok(())
+}
+
+// https://html.spec.whatwg.org/multipage/#dom-context-2d-drawimage
+/// Clips `source_rect` to an image of `image_size` and moves and scales `dest_rect`
+/// along, the source is then rounded out to whole pixels. Negative sizes are turned
+/// around first, `None` when nothing of the image is drawn.
+fn clip_to_image(image_size: Size2D<f64>, source_rect: Rect<f64>, dest_rect: Rect<f64>)
+                 -> Option<(Rect<f64>, Rect<f64>)> {
+  let normalize = |rect: Rect<f64>| {
+    let min = Point2D::new(rect.min_x().min(rect.max_x()), rect.min_y().min(rect.max_y()));
+    Rect::new(min, Size2D::new(rect.size.width.abs(), rect.size.height.abs()))
+  };
+  let (source_rect, dest_rect) = (normalize(source_rect), normalize(dest_rect));
+  let image_rect = Rect::new(Point2D::zero(), image_size);
+  let clipped = match source_rect.intersection(&image_rect) {
+    Some(clipped) if !clipped.is_empty() => clipped,
+    _ => return None,
+  };
+  let scale_x = dest_rect.size.width / source_rect.size.width;
+  let scale_y = dest_rect.size.height / source_rect.size.height;
+  let dest_rect = Rect::new(
+    Point2D::new(dest_rect.origin.x + (clipped.origin.x - source_rect.origin.x) * scale_x,
+                 dest_rect.origin.y + (clipped.origin.y - source_rect.origin.y) * scale_y),
+    Size2D::new(clipped.size.width * scale_x, clipped.size.height * scale_y));
+  Some((clipped.round_out(), dest_rect))
+}
+
 /// Used by drawImage to get rid of the extra pixels of the image data that
 /// won't be copied to the canvas
 /// image_data: Color pixel data of the image
@@ -829,6 +1523,43 @@ fn crop_image(image_data: Vec<u8>,
   new_image_data
 }
 
+/// `DrawTarget::stroke` starting the dashes `dash_offset` into the pattern,
+/// azure_hl always passes a zero offset to Azure.
+fn stroke_with_dash_offset(draw_target: &DrawTarget,
+                           path: &Path,
+                           pattern: PatternRef,
+                           stroke_options: &StrokeOptions,
+                           dash_offset: AzFloat,
+                           draw_options: &DrawOptions) {
+  let azure_pattern = match pattern {
+    PatternRef::Color(p) => p.azure_color_pattern,
+    PatternRef::LinearGradient(p) => p.azure_linear_gradient_pattern,
+    PatternRef::RadialGradient(p) => p.azure_radial_gradient_pattern,
+    PatternRef::Surface(p) => p.azure_surface_pattern,
+  };
+  let azure_stroke_options = AzStrokeOptions {
+    mLineWidth: stroke_options.line_width,
+    mMiterLimit: stroke_options.miter_limit,
+    mDashPattern: stroke_options.mDashPattern.as_ptr(),
+    mDashLength: stroke_options.mDashPattern.len(),
+    mDashOffset: dash_offset,
+    mLineJoin: stroke_options.line_join as AzJoinStyle,
+    mLineCap: stroke_options.line_cap as AzCapStyle,
+  };
+  let azure_draw_options = AzDrawOptions {
+    mAlpha: draw_options.alpha,
+    mCompositionOp: draw_options.composition as AzCompositionOp,
+    mAntialiasMode: draw_options.antialias as AzAntialiasMode,
+  };
+  // Safety: the draw target, path and pattern are live azure_hl objects borrowed for
+  // the call, and the dash pattern pointed to by the stroke options outlives it. Azure
+  // copies the options and does not keep any of the pointers after returning.
+  unsafe {
+    AzDrawTargetStroke(draw_target.azure_draw_target, path.azure_path, azure_pattern,
+                       &azure_stroke_options, &azure_draw_options);
+  }
+}
+
 /// It writes an image to the destination target
 /// draw_target: the destination target where the image_data will be copied
 /// image_data: Pixel information of the image to be written. It takes RGBA8
@@ -902,6 +1633,9 @@ impl ToAzureStyle for CompositionOrBlending {
   }
 }
 
+/// Patterns are built in user space, azure applies the transform of the draw target
+/// when painting. Gradient and pattern coordinates are thus interpreted with the
+/// transform in effect at paint time like browsers, not the one at creation time.
 pub trait ToAzurePattern {
   fn to_azure_pattern(&self, drawtarget: &DrawTarget) -> Option<Pattern>;
 }
@@ -941,6 +1675,21 @@ impl ToAzurePattern for FillOrStrokeStyle {
           drawtarget.create_gradient_stops(&gradient_stops, ExtendMode::Clamp),
           &Transform2D::identity())))
       },
+      FillOrStrokeStyle::ConicGradient(ref conic_gradient_style) => {
+        // azure has no conic gradient, it is drawn into a surface covering the
+        // draw target for its current transform
+        let size = drawtarget.get_size();
+        let to_user = match drawtarget.get_transform().inverse() {
+          Some(to_user) => to_user,
+          None => return Some(Pattern::Color(ColorPattern::new(Color::transparent()))),
+        };
+        let pixels = render_conic_gradient(conic_gradient_style, size, &to_user);
+        drawtarget.create_source_surface_from_data(&pixels, size, size.width * 4, SurfaceFormat::B8G8R8A8)
+                  .map(|source_surface| {
+            // repeated as the surface covers the whole draw target
+            Pattern::Surface(SurfacePattern::new(source_surface.azure_source_surface, true, true, &to_user))
+          })
+      },
       FillOrStrokeStyle::Surface(ref surface_style) => {
         drawtarget.create_source_surface_from_data(&surface_style.surface_data,
                                                     surface_style.surface_size,
@@ -951,13 +1700,24 @@ impl ToAzurePattern for FillOrStrokeStyle {
               source_surface.azure_source_surface,
               surface_style.repeat_x,
               surface_style.repeat_y,
-              &Transform2D::identity()))
+              &surface_style.transform))
             })
       }
     }
   }
 }
 
+impl ToAzureStyle for FillRule {
+  type Target = AzureFillRule;
+
+  fn to_azure_style(self) -> AzureFillRule {
+    match self {
+      FillRule::Nonzero => AzureFillRule::Winding,
+      FillRule::Evenodd => AzureFillRule::EvenOdd,
+    }
+  }
+}
+
 impl ToAzureStyle for LineCapStyle {
   type Target = CapStyle;
 
@@ -1040,11 +1800,159 @@ pub fn byte_swap(data: &mut [u8]) {
 
 #[cfg(test)]
 mod context_2d_test {
-  use euclid::{Size2D};
-  use super::{Context2d};
+  use std::f64::{INFINITY, NAN};
+  use std::sync::{Arc};
+  use std::sync::mpsc::{channel};
+  use std::thread;
+
+  use euclid::{Point2D, Rect, Size2D, Vector2D};
+  use canvas::{FontFace};
+  use fontrenderer::{shared_font_database, FontMetrics};
+  use fontrenderer::test_font::{build_test_face};
+  use super::{Canvas2dMsg, Context2d, CanvasError, Direction, FilterFunction, TextAlign, TextBaseline};
+  use super::{is_cluster_extender, orientation_runs, rotate_rect, small_caps_runs};
+  use super::{text_align_offset, text_baseline_offset};
 
   #[test]
   fn new_context_2d_check() {
     Context2d::new(Size2D::new(1920, 1080));
   }
+
+  #[test]
+  fn canvases_should_share_parsed_faces() {
+    // each canvas lives on its own thread
+    let font_key = || thread::spawn(|| {
+      Context2d::new(Size2D::new(10, 10)).layout_text("a").unwrap().runs[0].font_key
+    }).join().unwrap();
+    assert_eq!(font_key(), font_key());
+  }
+
+  #[test]
+  fn mixed_text_should_split_by_covering_face() {
+    let data = Arc::new(build_test_face(&['人', '中']));
+    shared_font_database().register_face(&FontFace::new("Test CJK"), data).unwrap();
+    let mut ctx = Context2d::new(Size2D::new(100, 20));
+    ctx.set_font_style("20px sans-serif");
+    let layout = ctx.layout_text("a人中b").unwrap();
+    let keys: Vec<_> = layout.runs.iter().map(|run| run.font_key).collect();
+    assert_eq!(keys.len(), 3);
+    assert_eq!(keys[0], keys[2]);
+    assert_ne!(keys[0], keys[1]);
+    assert_eq!(layout.runs[1].glyphs.len(), 2);
+    // the glyphs of the fallback face are half an em wide
+    assert!((layout.runs[2].glyphs[0].x - layout.runs[1].glyphs[0].x - 20.0).abs() < 0.01);
+    assert_eq!(ctx.fill_text("a人中b".to_string(), 0.0, 10.0, None), Ok(()));
+  }
+
+  #[test]
+  fn put_image_data_should_reject_mismatched_buffer() {
+    let mut ctx = Context2d::new(Size2D::new(10, 10));
+    let size = Size2D::new(2.0, 2.0);
+    let result = ctx.put_image_data(vec![0; 4], Vector2D::zero(), size, Rect::new(Point2D::zero(), size));
+    assert_eq!(result, Err(CanvasError::InvalidImageData { expected: 16, actual: 4 }));
+  }
+
+  #[test]
+  fn image_messages_should_reject_bad_input() {
+    let mut ctx = Context2d::new(Size2D::new(10, 10));
+    let size = Size2D::new(2.0, 2.0);
+    let rect = Rect::new(Point2D::zero(), size);
+    let nan_rect = Rect::new(Point2D::new(NAN, 0.0), size);
+    let draw_image = |data, source_rect| Canvas2dMsg::DrawImage(data, size, rect, source_rect, false);
+    let is_invalid_argument = |result: Result<(), CanvasError>| match result {
+      Err(CanvasError::InvalidArgument(_)) => true,
+      _ => false,
+    };
+
+    assert!(is_invalid_argument(ctx.handle_canvas2d_msg(draw_image(vec![0; 16], nan_rect))));
+    assert_eq!(ctx.handle_canvas2d_msg(draw_image(vec![0; 4], rect)),
+               Err(CanvasError::InvalidImageData { expected: 16, actual: 4 }));
+    let offset = Vector2D::new(INFINITY, 0.0);
+    assert!(is_invalid_argument(ctx.put_image_data(vec![0; 16], offset, size, rect)));
+    let (sender, receiver) = channel();
+    let huge_size = Size2D::new(1e12, 10.0);
+    assert!(is_invalid_argument(ctx.handle_canvas2d_msg(Canvas2dMsg::GetImageData(rect.to_i32(), huge_size, sender))));
+    assert_eq!(receiver.recv().unwrap(), Vec::<u8>::new());
+  }
+
+  #[test]
+  fn draw_image_should_clip_source_to_the_image() {
+    let mut ctx = Context2d::new(Size2D::new(10, 10));
+    let size = Size2D::new(2.0, 2.0);
+    // the source goes past the right edge of the image, only its first column is drawn
+    let source_rect = Rect::new(Point2D::new(1.0, 0.0), Size2D::new(2.0, 2.0));
+    let dest_rect = Rect::new(Point2D::zero(), Size2D::new(4.0, 4.0));
+    let message = Canvas2dMsg::DrawImage(vec![255; 16], size, dest_rect, source_rect, false);
+    assert_eq!(ctx.handle_canvas2d_msg(message), Ok(()));
+    assert_eq!(ctx.read_pixels(Rect::new(Point2D::new(1, 1), Size2D::new(1, 1))), vec![255; 4]);
+    assert_eq!(ctx.read_pixels(Rect::new(Point2D::new(3, 1), Size2D::new(1, 1))), vec![0; 4]);
+    // a read past the canvas is clipped to it
+    assert_eq!(ctx.read_pixels(Rect::new(Point2D::new(8, 8), Size2D::new(100, 100))).len(), 2 * 2 * 4);
+  }
+
+  #[test]
+  fn text_align_should_follow_direction() {
+    assert_eq!(text_align_offset(TextAlign::Start, Direction::Inherit, 40.0), 0.0);
+    assert_eq!(text_align_offset(TextAlign::Start, Direction::Rtl, 40.0), -40.0);
+    assert_eq!(text_align_offset(TextAlign::End, Direction::Ltr, 40.0), -40.0);
+    assert_eq!(text_align_offset(TextAlign::Center, Direction::Rtl, 40.0), -20.0);
+    assert_eq!(text_align_offset(TextAlign::Left, Direction::Rtl, 40.0), 0.0);
+  }
+
+  #[test]
+  fn text_baseline_should_use_font_metrics() {
+    let metrics = FontMetrics { units_per_em: 10.0, ascent: 8.0, descent: 2.0, line_gap: 0.0 };
+    assert_eq!(text_baseline_offset(TextBaseline::Top, &metrics), 8.0);
+    assert_eq!(text_baseline_offset(TextBaseline::Middle, &metrics), 3.0);
+    assert_eq!(text_baseline_offset(TextBaseline::Alphabetic, &metrics), 0.0);
+    assert_eq!(text_baseline_offset(TextBaseline::Bottom, &metrics), -2.0);
+  }
+
+  #[test]
+  fn set_font_style_should_ignore_invalid_font() {
+    let mut ctx = Context2d::new(Size2D::new(10, 10));
+    ctx.set_font_style("bold 20px serif");
+    ctx.set_font_style("20px");
+    assert_eq!(ctx.state.font.font_size, 20.0);
+    assert_eq!(ctx.state.font.font_weight, 700);
+  }
+
+  #[test]
+  fn set_filter_should_ignore_invalid_filter() {
+    let mut ctx = Context2d::new(Size2D::new(10, 10));
+    ctx.set_filter("invert(100%)");
+    ctx.set_filter("invert(100%) glow(2px)");
+    assert_eq!(ctx.state.filter.functions, vec![FilterFunction::Invert(1.0)]);
+    ctx.set_filter("none");
+    assert!(ctx.state.filter.is_none());
+  }
+
+  #[test]
+  fn marks_should_extend_clusters() {
+    assert!(is_cluster_extender('\u{301}'));
+    assert!(is_cluster_extender('\u{200d}'));
+    assert!(!is_cluster_extender('a'));
+  }
+
+  #[test]
+  fn vertical_text_should_split_by_orientation() {
+    // "日本" then "ab" then "語" with a combining mark after "b"
+    let text = "\u{65e5}\u{672c}ab\u{301}\u{8a9e}";
+    assert_eq!(orientation_runs(text, 0..text.len()), vec![(0..6, true), (6..10, false), (10..13, true)]);
+    assert_eq!(orientation_runs(text, 6..8), vec![(6..8, false)]);
+  }
+
+  #[test]
+  fn small_caps_should_scale_lowercase_capitals() {
+    assert_eq!(small_caps_runs("Hello W"), vec![
+      ("H".to_string(), false), ("ELLO".to_string(), true), (" W".to_string(), false),
+    ]);
+    assert_eq!(small_caps_runs("stra\u{df}e"), vec![("STRASSE".to_string(), true)]);
+  }
+
+  #[test]
+  fn rotate_rect_should_turn_clockwise() {
+    let rect = Rect::new(Point2D::new(1.0, -2.0), Size2D::new(4.0, 10.0));
+    assert_eq!(rotate_rect(&rect), Rect::new(Point2D::new(-2.0, -5.0), Size2D::new(10.0, 4.0)));
+  }
 }
@@ -0,0 +1,46 @@
+use std::error::{Error};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum CanvasError {
+  /// The canvas thread has stopped and no longer accepts messages.
+  Disconnected,
+  Encode(String),
+  FontLoad(String),
+  FontNotFound(String),
+  GlyphNotFound(char),
+  GlyphOutline(char),
+  /// A message carried values the canvas cannot draw with, like NaN coordinates.
+  InvalidArgument(String),
+  InvalidImageData { expected: usize, actual: usize },
+}
+
+impl fmt::Display for CanvasError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      CanvasError::Disconnected => write!(f, "canvas thread has been closed"),
+      CanvasError::Encode(ref reason) => write!(f, "fail to encode image: {}", reason),
+      CanvasError::FontLoad(ref family) => write!(f, "fail to load font: {}", family),
+      CanvasError::FontNotFound(ref family) => write!(f, "font not found: {}", family),
+      CanvasError::GlyphNotFound(c) => write!(f, "no glyph for char: {:?}", c),
+      CanvasError::GlyphOutline(c) => write!(f, "fail to get glyph outline for char: {:?}", c),
+      CanvasError::InvalidArgument(ref reason) => write!(f, "invalid argument: {}", reason),
+      CanvasError::InvalidImageData { expected, actual } => {
+        write!(f, "image data length mismatch, expected {} bytes but got {}", expected, actual)
+      },
+    }
+  }
+}
+
+impl Error for CanvasError {}
+
+#[cfg(test)]
+mod canvas_error_tests {
+  use super::*;
+
+  #[test]
+  fn should_format_invalid_image_data() {
+    let err = CanvasError::InvalidImageData { expected: 16, actual: 4 };
+    assert_eq!(err.to_string(), "image data length mismatch, expected 16 bytes but got 4");
+  }
+}
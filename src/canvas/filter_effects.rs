@@ -0,0 +1,243 @@
+use cssparser::{RGBA};
+use euclid::{Size2D, Vector2D};
+
+use super::paintstate::{CanvasFilter, FilterFunction};
+
+/// Applies `filter` to the premultiplied B8G8R8A8 `pixels` of a target of `size`,
+/// pixels outside of the target are transparent.
+pub fn apply_filter(filter: &CanvasFilter, pixels: &mut [u8], size: Size2D<i32>) {
+  for function in &filter.functions {
+    match *function {
+      FilterFunction::Blur(deviation) => blur(pixels, size, deviation),
+      FilterFunction::DropShadow { offset, blur: deviation, color } => {
+        drop_shadow(pixels, size, offset, deviation, color)
+      },
+      ref function => {
+        for pixel in pixels.chunks_mut(4) {
+          let alpha = pixel[3] as f32 / 255.0;
+          if alpha == 0.0 {
+            continue;
+          }
+          let rgba = [pixel[2] as f32 / 255.0 / alpha, pixel[1] as f32 / 255.0 / alpha,
+                      pixel[0] as f32 / 255.0 / alpha, alpha];
+          let rgba = filter_color(function, rgba);
+          let alpha = rgba[3].max(0.0).min(1.0);
+          let premultiply = |c: f32| (c.max(0.0).min(1.0) * alpha * 255.0).round() as u8;
+          pixel[0] = premultiply(rgba[2]);
+          pixel[1] = premultiply(rgba[1]);
+          pixel[2] = premultiply(rgba[0]);
+          pixel[3] = (alpha * 255.0).round() as u8;
+        }
+      },
+    }
+  }
+}
+
+/// How many pixels `filter` spreads a drawing by on each side.
+pub fn filter_extent(filter: &CanvasFilter) -> f32 {
+  filter.functions.iter().map(|function| match *function {
+    FilterFunction::Blur(deviation) => (deviation.max(0.0) * 3.0).ceil(),
+    FilterFunction::DropShadow { offset, blur: deviation, .. } => {
+      offset.x.round().abs().max(offset.y.round().abs()) + (deviation.max(0.0) * 3.0).ceil()
+    },
+    _ => 0.0,
+  }).sum()
+}
+
+// https://drafts.fxtf.org/filter-effects/#ShorthandEquivalents
+/// Unpremultiplied RGBA through a color function, the result is not clamped.
+fn filter_color(function: &FilterFunction, rgba: [f32; 4]) -> [f32; 4] {
+  let (r, g, b, a) = (rgba[0], rgba[1], rgba[2], rgba[3]);
+  let matrix = |m: [[f32; 3]; 3]| [
+    m[0][0] * r + m[0][1] * g + m[0][2] * b,
+    m[1][0] * r + m[1][1] * g + m[1][2] * b,
+    m[2][0] * r + m[2][1] * g + m[2][2] * b,
+    a,
+  ];
+  let linear = |slope: f32, intercept: f32| [r * slope + intercept, g * slope + intercept, b * slope + intercept, a];
+  match *function {
+    FilterFunction::Brightness(amount) => linear(amount, 0.0),
+    FilterFunction::Contrast(amount) => linear(amount, 0.5 - 0.5 * amount),
+    FilterFunction::Invert(amount) => linear(1.0 - 2.0 * amount, amount),
+    FilterFunction::Opacity(amount) => [r, g, b, a * amount],
+    FilterFunction::Grayscale(amount) => {
+      let s = 1.0 - amount;
+      matrix([
+        [0.2126 + 0.7874 * s, 0.7152 - 0.7152 * s, 0.0722 - 0.0722 * s],
+        [0.2126 - 0.2126 * s, 0.7152 + 0.2848 * s, 0.0722 - 0.0722 * s],
+        [0.2126 - 0.2126 * s, 0.7152 - 0.7152 * s, 0.0722 + 0.9278 * s],
+      ])
+    },
+    FilterFunction::Sepia(amount) => {
+      let s = 1.0 - amount;
+      matrix([
+        [0.393 + 0.607 * s, 0.769 - 0.769 * s, 0.189 - 0.189 * s],
+        [0.349 - 0.349 * s, 0.686 + 0.314 * s, 0.168 - 0.168 * s],
+        [0.272 - 0.272 * s, 0.534 - 0.534 * s, 0.131 + 0.869 * s],
+      ])
+    },
+    FilterFunction::Saturate(s) => matrix([
+      [0.213 + 0.787 * s, 0.715 - 0.715 * s, 0.072 - 0.072 * s],
+      [0.213 - 0.213 * s, 0.715 + 0.285 * s, 0.072 - 0.072 * s],
+      [0.213 - 0.213 * s, 0.715 - 0.715 * s, 0.072 + 0.928 * s],
+    ]),
+    FilterFunction::HueRotate(angle) => {
+      let (sin, cos) = angle.to_radians().sin_cos();
+      matrix([
+        [0.213 + cos * 0.787 - sin * 0.213, 0.715 - cos * 0.715 - sin * 0.715, 0.072 - cos * 0.072 + sin * 0.928],
+        [0.213 - cos * 0.213 + sin * 0.143, 0.715 + cos * 0.285 + sin * 0.140, 0.072 - cos * 0.072 - sin * 0.283],
+        [0.213 - cos * 0.213 - sin * 0.787, 0.715 - cos * 0.715 + sin * 0.715, 0.072 + cos * 0.928 + sin * 0.072],
+      ])
+    },
+    FilterFunction::Blur(_) | FilterFunction::DropShadow { .. } => rgba,
+  }
+}
+
+/// Gaussian blur of premultiplied pixels, one pass per axis.
+fn blur(pixels: &mut [u8], size: Size2D<i32>, deviation: f32) {
+  if deviation <= 0.0 {
+    return;
+  }
+  let radius = (deviation * 3.0).ceil() as usize;
+  let mut kernel: Vec<f32> = (0..radius * 2 + 1).map(|i| {
+    let d = i as f32 - radius as f32;
+    (-d * d / (2.0 * deviation * deviation)).exp()
+  }).collect();
+  let total: f32 = kernel.iter().sum();
+  for weight in &mut kernel {
+    *weight /= total;
+  }
+
+  let (width, height) = (size.width.max(0) as usize, size.height.max(0) as usize);
+  let source: Vec<f32> = pixels.iter().map(|c| *c as f32).collect();
+  let horizontal = convolve(&source, width, height, &kernel, true);
+  let blurred = convolve(&horizontal, width, height, &kernel, false);
+  for (c, value) in pixels.iter_mut().zip(blurred) {
+    *c = value.round().max(0.0).min(255.0) as u8;
+  }
+}
+
+fn convolve(source: &[f32], width: usize, height: usize, kernel: &[f32], horizontal: bool) -> Vec<f32> {
+  let radius = (kernel.len() / 2) as isize;
+  let mut result = vec![0.0; source.len()];
+  for y in 0..height {
+    for x in 0..width {
+      let mut sum = [0.0f32; 4];
+      for (i, weight) in kernel.iter().enumerate() {
+        let offset = i as isize - radius;
+        let (sx, sy) = if horizontal {
+          (x as isize + offset, y as isize)
+        } else {
+          (x as isize, y as isize + offset)
+        };
+        if sx < 0 || sy < 0 || sx >= width as isize || sy >= height as isize {
+          continue;
+        }
+        let index = (sy as usize * width + sx as usize) * 4;
+        for c in 0..4 {
+          sum[c] += source[index + c] * weight;
+        }
+      }
+      let index = (y * width + x) * 4;
+      result[index..index + 4].copy_from_slice(&sum);
+    }
+  }
+  result
+}
+
+/// Draws the pixels over their alpha painted with `color`, blurred and moved by `offset`.
+fn drop_shadow(pixels: &mut [u8], size: Size2D<i32>, offset: Vector2D<f32>, deviation: f32, color: RGBA) {
+  let (width, height) = (size.width.max(0) as isize, size.height.max(0) as isize);
+  let (dx, dy) = (offset.x.round() as isize, offset.y.round() as isize);
+  let alpha = color.alpha as f32 / 255.0;
+  let shadow_color = [color.blue as f32 * alpha, color.green as f32 * alpha, color.red as f32 * alpha, alpha * 255.0];
+
+  let mut shadow = vec![0; pixels.len()];
+  for y in 0..height {
+    for x in 0..width {
+      let (sx, sy) = (x - dx, y - dy);
+      if sx < 0 || sy < 0 || sx >= width || sy >= height {
+        continue;
+      }
+      let coverage = pixels[((sy * width + sx) * 4 + 3) as usize] as f32 / 255.0;
+      let index = ((y * width + x) * 4) as usize;
+      for c in 0..4 {
+        shadow[index + c] = (shadow_color[c] * coverage).round() as u8;
+      }
+    }
+  }
+  blur(&mut shadow, size, deviation);
+
+  for (pixel, shadow) in pixels.chunks_mut(4).zip(shadow.chunks(4)) {
+    let uncovered = 1.0 - pixel[3] as f32 / 255.0;
+    for c in 0..4 {
+      pixel[c] = (pixel[c] as f32 + shadow[c] as f32 * uncovered).round().min(255.0) as u8;
+    }
+  }
+}
+
+#[cfg(test)]
+mod filter_effects_tests {
+  use super::*;
+
+  const RED: [u8; 4] = [0, 0, 255, 255];
+
+  fn filtered(functions: Vec<FilterFunction>, mut pixels: Vec<u8>, size: Size2D<i32>) -> Vec<u8> {
+    apply_filter(&CanvasFilter { functions }, &mut pixels, size);
+    pixels
+  }
+
+  #[test]
+  fn should_apply_color_functions_in_order() {
+    let size = Size2D::new(1, 1);
+    assert_eq!(filtered(vec![FilterFunction::Grayscale(1.0)], RED.to_vec(), size), vec![54, 54, 54, 255]);
+    assert_eq!(filtered(vec![FilterFunction::Invert(1.0)], RED.to_vec(), size), vec![255, 255, 0, 255]);
+    assert_eq!(filtered(vec![FilterFunction::Opacity(0.5)], RED.to_vec(), size), vec![0, 0, 128, 128]);
+    assert_eq!(filtered(vec![FilterFunction::Brightness(0.5), FilterFunction::Invert(1.0)], RED.to_vec(), size),
+               vec![255, 255, 128, 255]);
+    assert_eq!(filtered(vec![FilterFunction::HueRotate(0.0), FilterFunction::Saturate(1.0)], RED.to_vec(), size),
+               RED.to_vec());
+  }
+
+  #[test]
+  fn color_functions_should_keep_transparent_pixels() {
+    let functions = vec![FilterFunction::Invert(1.0), FilterFunction::Brightness(2.0)];
+    assert_eq!(filtered(functions, vec![0, 0, 0, 0], Size2D::new(1, 1)), vec![0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn blur_should_spread_symmetrically() {
+    let mut pixels = vec![0; 5 * 4];
+    pixels[8..12].copy_from_slice(&RED);
+    let pixels = filtered(vec![FilterFunction::Blur(1.0)], pixels, Size2D::new(5, 1));
+    let alphas: Vec<u8> = pixels.chunks(4).map(|pixel| pixel[3]).collect();
+    assert!(alphas[2] < 255);
+    assert!(alphas[1] > 0);
+    assert_eq!(alphas[1], alphas[3]);
+    assert_eq!(alphas[0], alphas[4]);
+  }
+
+  #[test]
+  fn drop_shadow_should_go_under_the_image() {
+    let mut pixels = vec![0; 3 * 4];
+    pixels[..4].copy_from_slice(&RED);
+    let shadow = FilterFunction::DropShadow {
+      offset: Vector2D::new(1.0, 0.0),
+      blur: 0.0,
+      color: RGBA::new(0, 0, 255, 255),
+    };
+    assert_eq!(filtered(vec![shadow], pixels, Size2D::new(3, 1)), vec![0, 0, 255, 255, 255, 0, 0, 255, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn extent_should_add_up_blurs_and_shadow_offsets() {
+    let shadow = FilterFunction::DropShadow {
+      offset: Vector2D::new(-4.0, 2.0),
+      blur: 1.0,
+      color: RGBA::new(0, 0, 255, 255),
+    };
+    let filter = CanvasFilter { functions: vec![FilterFunction::Blur(0.5), FilterFunction::Invert(1.0), shadow] };
+    assert_eq!(filter_extent(&filter), 2.0 + 4.0 + 3.0);
+    assert_eq!(filter_extent(&CanvasFilter::default()), 0.0);
+  }
+}
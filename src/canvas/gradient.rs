@@ -0,0 +1,97 @@
+use std::cmp::{Ordering};
+use std::f64::consts::{PI};
+
+use euclid::{Point2D, Size2D, Transform2D};
+
+use super::canvas_trait::{CanvasGradientStop, ConicGradientStyle};
+
+/// Pixels of a conic gradient covering a draw target of `size` in premultiplied
+/// B8G8R8A8, `to_user` maps the device pixels to the user space of the gradient.
+pub fn render_conic_gradient(style: &ConicGradientStyle, size: Size2D<i32>, to_user: &Transform2D<f32>) -> Vec<u8> {
+  let mut stops = style.stops.clone();
+  stops.sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap_or(Ordering::Equal));
+
+  let mut pixels = Vec::with_capacity((size.width.max(0) * size.height.max(0) * 4) as usize);
+  for y in 0..size.height {
+    for x in 0..size.width {
+      let p = to_user.transform_point(&Point2D::new(x as f32 + 0.5, y as f32 + 0.5));
+      // clockwise from the start angle in the y down canvas space
+      let turn = ((p.y as f64 - style.y).atan2(p.x as f64 - style.x) - style.start_angle) / (2.0 * PI);
+      let (r, g, b, a) = gradient_color(&stops, turn - turn.floor());
+      let premultiply = |c: f64| (c * a * 255.0).round() as u8;
+      pixels.extend_from_slice(&[premultiply(b), premultiply(g), premultiply(r), (a * 255.0).round() as u8]);
+    }
+  }
+  pixels
+}
+
+/// Color at `offset` of sorted `stops`, unpremultiplied in the range of [0, 1].
+/// Gradients without stops are transparent black.
+fn gradient_color(stops: &[CanvasGradientStop], offset: f64) -> (f64, f64, f64, f64) {
+  let color = |stop: &CanvasGradientStop| (
+    stop.color.red as f64 / 255.0,
+    stop.color.green as f64 / 255.0,
+    stop.color.blue as f64 / 255.0,
+    stop.color.alpha as f64 / 255.0,
+  );
+  let (first, last) = match (stops.first(), stops.last()) {
+    (Some(first), Some(last)) => (first, last),
+    _ => return (0.0, 0.0, 0.0, 0.0),
+  };
+  if offset <= first.offset {
+    return color(first);
+  }
+  for pair in stops.windows(2) {
+    if offset < pair[1].offset {
+      let t = (offset - pair[0].offset) / (pair[1].offset - pair[0].offset);
+      let (from, to) = (color(&pair[0]), color(&pair[1]));
+      let lerp = |a: f64, b: f64| a + (b - a) * t;
+      return (lerp(from.0, to.0), lerp(from.1, to.1), lerp(from.2, to.2), lerp(from.3, to.3));
+    }
+  }
+  color(last)
+}
+
+#[cfg(test)]
+mod gradient_tests {
+  use super::*;
+  use cssparser::{RGBA};
+
+  fn stops() -> Vec<CanvasGradientStop> {
+    vec![
+      CanvasGradientStop { offset: 0.0, color: RGBA::new(255, 0, 0, 255) },
+      CanvasGradientStop { offset: 0.5, color: RGBA::new(0, 0, 255, 255) },
+      CanvasGradientStop { offset: 0.5, color: RGBA::new(0, 255, 0, 255) },
+    ]
+  }
+
+  #[test]
+  fn should_interpolate_stops() {
+    assert_eq!(gradient_color(&stops(), 0.25), (0.5, 0.0, 0.5, 1.0));
+    // the last of two stops at the same offset wins
+    assert_eq!(gradient_color(&stops(), 0.5), (0.0, 1.0, 0.0, 1.0));
+    assert_eq!(gradient_color(&stops(), 0.9), (0.0, 1.0, 0.0, 1.0));
+    assert_eq!(gradient_color(&[], 0.5), (0.0, 0.0, 0.0, 0.0));
+  }
+
+  #[test]
+  fn should_start_at_start_angle_and_go_clockwise() {
+    // a 2x2 target around the center
+    let style = ConicGradientStyle::new(0.0, 1.0, 1.0, stops());
+    let pixels = render_conic_gradient(&style, Size2D::new(2, 2), &Transform2D::identity());
+    let colors: Vec<&[u8]> = pixels.chunks(4).collect();
+    // bottom right is an eighth of a turn away from the start
+    assert_eq!(colors[3], &[64, 0, 191, 255]);
+    assert_eq!(colors[2], &[191, 0, 64, 255]);
+    // top left is past the hard stop
+    assert_eq!(colors[0], &[0, 255, 0, 255]);
+  }
+
+  #[test]
+  fn should_follow_user_space() {
+    let style = ConicGradientStyle::new(0.0, 2.0, 2.0, stops());
+    let translated = render_conic_gradient(&style, Size2D::new(2, 2), &Transform2D::create_translation(1.0, 1.0));
+    let style = ConicGradientStyle::new(0.0, 1.0, 1.0, stops());
+    assert_eq!(translated, render_conic_gradient(&style, Size2D::new(2, 2), &Transform2D::identity()));
+  }
+}
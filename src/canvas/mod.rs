@@ -1,16 +1,55 @@
+use std::fs::{File};
+use std::io::{Read};
+use std::path::{Path};
+use std::sync::{Arc};
+
+use fontrenderer::{shared_font_database};
+
 mod canvas_element;
+mod canvas_rendering_context_2d;
 mod canvas_trait;
 mod context_2d;
+mod error;
+mod filter_effects;
+mod gradient;
 mod paintstate;
+mod path2d;
 mod get_target;
 #[cfg(target_os="macos")] mod get_target_cgl;
 #[cfg(target_os="linux")] mod get_target_glx;
 
 pub use self::canvas_element::{CanvasElement, CanvasContextType};
+pub use self::canvas_rendering_context_2d::{CanvasRenderingContext2D};
 pub use self::paintstate::*;
 pub use self::canvas_trait::*;
 pub use self::context_2d::*;
+pub use self::error::{CanvasError};
+pub use self::path2d::{Path2D, PathOp};
+pub use fontrenderer::{GlyphCacheStats};
 
 pub fn create_canvas(width: i32, height: i32, ctx_type: CanvasContextType) -> CanvasElement {
   CanvasElement::new(width, height, ctx_type).unwrap()
 }
+
+/// Registers a font for every canvas of the process.
+pub fn register_font(data: Vec<u8>, face: &FontFace) -> Result<(), CanvasError> {
+  shared_font_database().register_face(face, Arc::new(data)).map(|_| ())
+}
+
+pub fn register_font_file<P: AsRef<Path>>(path: P, face: &FontFace) -> Result<(), CanvasError> {
+  register_font(read_font_file(path)?, face)
+}
+
+/// Families tried for chars missing from the families of the css `font`,
+/// before looking through every installed font.
+pub fn set_fallback_families(families: &[&str]) {
+  shared_font_database().set_fallback_families(families.iter().map(|f| f.to_string()).collect());
+}
+
+fn read_font_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, CanvasError> {
+  let mut data = vec![];
+  File::open(path.as_ref())
+    .and_then(|mut file| file.read_to_end(&mut data))
+    .map_err(|e| CanvasError::FontLoad(format!("{}: {}", path.as_ref().display(), e)))?;
+  Ok(data)
+}
@@ -0,0 +1,41 @@
+use cssparser::{RGBA};
+use euclid::{Vector2D};
+
+use csshelper::{parse_filter};
+
+// https://drafts.fxtf.org/filter-effects/#filter-functions
+/// Amounts are factors, 1 leaves the image unchanged except for the functions
+/// going from the image to a flat result like `grayscale` and `invert`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterFunction {
+  /// Standard deviation of the gaussian blur in pixels.
+  Blur(f32),
+  Brightness(f32),
+  Contrast(f32),
+  /// `blur` is the standard deviation, half the css blur radius like shadows.
+  DropShadow { offset: Vector2D<f32>, blur: f32, color: RGBA },
+  Grayscale(f32),
+  /// Angle in degrees.
+  HueRotate(f32),
+  Invert(f32),
+  Opacity(f32),
+  Saturate(f32),
+  Sepia(f32),
+}
+
+/// The `filter` of the context, functions are applied in order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CanvasFilter {
+  pub functions: Vec<FilterFunction>,
+}
+
+impl CanvasFilter {
+  /// Parses a css `filter`, `none` is the empty chain and invalid values are rejected.
+  pub fn new(filter: &str) -> Result<CanvasFilter, ()> {
+    parse_filter(filter)
+  }
+
+  pub fn is_none(&self) -> bool {
+    self.functions.is_empty()
+  }
+}
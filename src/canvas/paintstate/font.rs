@@ -1,20 +1,40 @@
-use csshelper::{parse_fonts_style};
+use csshelper::{parse_fonts_style, SANS_SERIF_FONT_FAMILY};
 
 #[derive(Debug, Clone)]
 pub struct Font {
   pub font_size: f32,
   pub font_style: FontStyle,
+  /// Numeric weight in the range of [1, 1000], 400 is normal.
+  pub font_weight: u16,
+  pub font_stretch: FontStretch,
   pub font_family: String,
+  /// Families after the first one in the `font` family list.
+  pub fallback_families: Vec<String>,
   pub font_variant: FontVariant,
 }
 
+impl Default for Font {
+  fn default() -> Font {
+    Font {
+      font_size: 10.0,
+      font_style: FontStyle::Normal,
+      font_weight: 400,
+      font_stretch: FontStretch::Normal,
+      font_family: SANS_SERIF_FONT_FAMILY.to_string(),
+      fallback_families: vec![],
+      font_variant: FontVariant::Normal,
+    }
+  }
+}
+
 impl Font {
-  pub fn new(font_rules: &str) -> Font {
+  /// Parses a css `font` shorthand, invalid values are rejected.
+  pub fn new(font_rules: &str) -> Result<Font, ()> {
     parse_fonts_style(font_rules)
   }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FontStyle {
   Normal,
   Italic,
@@ -27,12 +47,52 @@ pub enum FontVariant {
   SmallCaps,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FontStretch {
+  UltraCondensed,
+  ExtraCondensed,
+  Condensed,
+  SemiCondensed,
+  Normal,
+  SemiExpanded,
+  Expanded,
+  ExtraExpanded,
+  UltraExpanded,
+}
+
+/// Descriptors of a registered font, like a css `@font-face` rule.
+/// Descriptors left to `None` are read from the font file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontFace {
+  pub family: String,
+  pub weight: Option<u16>,
+  pub style: Option<FontStyle>,
+  pub stretch: Option<FontStretch>,
+  /// Face of a font collection (ttc or otc) to register, 0 for single font files.
+  pub index: u32,
+}
+
+impl FontFace {
+  pub fn new(family: &str) -> FontFace {
+    FontFace {
+      family: family.to_string(),
+      weight: None,
+      style: None,
+      stretch: None,
+      index: 0,
+    }
+  }
+}
+
 #[cfg(test)]
 mod font_test {
   use super::{Font};
 
   #[test]
   fn font_default_check() {
-    Font::new("10px sans-serif");
+    let font = Font::new("10px sans-serif").unwrap();
+    let default_font = Font::default();
+    assert_eq!(font.font_size, default_font.font_size);
+    assert_eq!(font.font_family, default_font.font_family);
   }
 }
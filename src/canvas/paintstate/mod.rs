@@ -1,12 +1,15 @@
+mod filter;
 mod font;
 
 use std::default::Default;
 use azure::azure_hl::{Pattern, ColorPattern, Color, StrokeOptions, JoinStyle, CapStyle, DrawOptions};
 use azure::azure_hl::{AntialiasMode, CompositionOp};
 use azure::{AzFloat};
-use euclid::{Transform2D};
+use euclid::{Point2D, Transform2D};
 use cssparser::{RGBA};
+use super::canvas_trait::{ConicGradientStyle, Direction, TextAlign, TextBaseline, WritingMode};
 use super::context_2d::{ToAzureStyle};
+pub use self::filter::*;
 pub use self::font::*;
 
 #[derive(Debug, Clone)]
@@ -14,13 +17,27 @@ pub struct PaintState<'a> {
   pub draw_options: DrawOptions,
   pub fill_style: Pattern,
   pub stroke_style: Pattern,
+  /// Conic gradients of the styles and the transform their pattern was last drawn for,
+  /// `None` until the first paint.
+  pub fill_conic_gradient: Option<(ConicGradientStyle, Option<Transform2D<f32>>)>,
+  pub stroke_conic_gradient: Option<(ConicGradientStyle, Option<Transform2D<f32>>)>,
+  /// Painted area of surface styles, see `SurfaceStyle::painted_area`.
+  pub fill_pattern_area: Option<[Point2D<f32>; 4]>,
+  pub stroke_pattern_area: Option<[Point2D<f32>; 4]>,
   pub stroke_opts: StrokeOptions<'a>,
+  pub line_dash: Vec<AzFloat>,
+  pub line_dash_offset: AzFloat,
   pub font: Font,
+  pub text_align: TextAlign,
+  pub text_baseline: TextBaseline,
+  pub direction: Direction,
+  pub writing_mode: WritingMode,
   pub transform: Transform2D<f32>,
   pub shadow_offset_x: f64,
   pub shadow_offset_y: f64,
   pub shadow_blur: f64,
   pub shadow_color: Color,
+  pub filter: CanvasFilter,
 }
 
 impl <'a> Default for PaintState<'a> {
@@ -31,13 +48,24 @@ impl <'a> Default for PaintState<'a> {
       draw_options: DrawOptions::new(1.0, CompositionOp::Over, AntialiasMode::Default),
       fill_style,
       stroke_style,
+      fill_conic_gradient: None,
+      stroke_conic_gradient: None,
+      fill_pattern_area: None,
+      stroke_pattern_area: None,
       stroke_opts: StrokeOptions::new(1.0, JoinStyle::MiterOrBevel, CapStyle::Butt, 10.0, &[]),
-      font: Font::new("10px sans-serif"),
+      line_dash: vec![],
+      line_dash_offset: 0.0,
+      font: Font::default(),
+      text_align: TextAlign::default(),
+      text_baseline: TextBaseline::default(),
+      direction: Direction::default(),
+      writing_mode: WritingMode::default(),
       transform: Transform2D::identity(),
       shadow_offset_x: 0.0,
       shadow_offset_y: 0.0,
       shadow_blur: 0.0,
       shadow_color: Color::transparent(),
+      filter: CanvasFilter::default(),
     }
   }
 }
@@ -46,6 +74,35 @@ impl <'a> PaintState <'a> {
   pub fn new() -> PaintState<'a> {
     PaintState::default()
   }
+
+  /// `stroke_opts` with the dash pattern of this state, the dash offset is
+  /// passed separately as azure_hl has no field for it.
+  pub fn stroke_options(&self) -> StrokeOptions {
+    StrokeOptions::new(self.stroke_opts.line_width,
+                       self.stroke_opts.line_join,
+                       self.stroke_opts.line_cap,
+                       self.stroke_opts.miter_limit,
+                       &self.line_dash)
+  }
+
+  // https://html.spec.whatwg.org/multipage/#dom-context-2d-setlinedash
+  pub fn set_line_dash(&mut self, segments: &[AzFloat]) {
+    if segments.iter().any(|s| !s.is_finite() || *s < 0.0) {
+      return;
+    }
+    let mut line_dash = segments.to_vec();
+    if line_dash.len() % 2 == 1 {
+      line_dash.extend_from_slice(segments);
+    }
+    self.line_dash = line_dash;
+  }
+
+  pub fn set_line_dash_offset(&mut self, offset: AzFloat) {
+    if !offset.is_finite() {
+      return;
+    }
+    self.line_dash_offset = offset;
+  }
 }
 
 impl ToAzureStyle for RGBA {
@@ -72,6 +129,29 @@ mod paint_state_test {
     pub azure_color_pattern: AzColorPatternRef,
   }
 
+  #[test]
+  fn should_set_line_dash() {
+    let mut state = PaintState::new();
+    state.set_line_dash(&[5.0, 10.0, 15.0]);
+    assert_eq!(state.line_dash, vec![5.0, 10.0, 15.0, 5.0, 10.0, 15.0]);
+    // invalid segments are ignored
+    state.set_line_dash(&[5.0, -1.0]);
+    state.set_line_dash(&[5.0, ::std::f32::NAN]);
+    assert_eq!(state.line_dash, vec![5.0, 10.0, 15.0, 5.0, 10.0, 15.0]);
+    state.set_line_dash(&[]);
+    assert!(state.stroke_options().mDashPattern.is_empty());
+  }
+
+  #[test]
+  fn should_keep_dash_offset_out_of_the_pattern() {
+    let mut state = PaintState::new();
+    state.set_line_dash(&[10.0, 5.0]);
+    state.set_line_dash_offset(4.0);
+    state.set_line_dash_offset(::std::f32::INFINITY);
+    assert_eq!(state.line_dash_offset, 4.0);
+    assert_eq!(state.stroke_options().mDashPattern, &[10.0, 5.0]);
+  }
+
   #[test]
   fn paint_state_default_check() {
     let state = PaintState::default();
@@ -0,0 +1,349 @@
+use std::f32::consts::{PI};
+
+use euclid::{Point2D, Rect, Size2D, Transform2D};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum PathOp {
+  MoveTo(Point2D<f32>),
+  LineTo(Point2D<f32>),
+  QuadraticCurveTo(Point2D<f32>, Point2D<f32>),
+  BezierCurveTo(Point2D<f32>, Point2D<f32>, Point2D<f32>),
+  Arc(Point2D<f32>, f32, f32, f32, bool),
+  ArcTo(Point2D<f32>, Point2D<f32>, f32),
+  Ellipse(Point2D<f32>, f32, f32, f32, f32, f32, bool),
+  Rect(Rect<f32>),
+  ClosePath,
+}
+
+/// A path recorded on the client side, it is sent to the canvas thread as a whole
+/// and can be filled, stroked, clipped or hit tested any number of times.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Path2D {
+  ops: Vec<PathOp>,
+}
+
+impl Path2D {
+  pub fn new() -> Path2D {
+    Path2D::default()
+  }
+
+  pub fn ops(&self) -> &[PathOp] {
+    &self.ops
+  }
+
+  pub fn move_to(&mut self, x: f32, y: f32) {
+    self.ops.push(PathOp::MoveTo(Point2D::new(x, y)));
+  }
+
+  pub fn line_to(&mut self, x: f32, y: f32) {
+    self.ops.push(PathOp::LineTo(Point2D::new(x, y)));
+  }
+
+  pub fn quadratic_curve_to(&mut self, cpx: f32, cpy: f32, x: f32, y: f32) {
+    self.ops.push(PathOp::QuadraticCurveTo(Point2D::new(cpx, cpy), Point2D::new(x, y)));
+  }
+
+  pub fn bezier_curve_to(&mut self, cp1x: f32, cp1y: f32, cp2x: f32, cp2y: f32, x: f32, y: f32) {
+    self.ops.push(PathOp::BezierCurveTo(Point2D::new(cp1x, cp1y), Point2D::new(cp2x, cp2y), Point2D::new(x, y)));
+  }
+
+  pub fn arc(&mut self, x: f32, y: f32, radius: f32, start_angle: f32, end_angle: f32, anticlockwise: bool) {
+    self.ops.push(PathOp::Arc(Point2D::new(x, y), radius, start_angle, end_angle, anticlockwise));
+  }
+
+  pub fn arc_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, radius: f32) {
+    self.ops.push(PathOp::ArcTo(Point2D::new(x1, y1), Point2D::new(x2, y2), radius));
+  }
+
+  pub fn ellipse(&mut self, x: f32, y: f32, radius_x: f32, radius_y: f32, rotation: f32,
+                 start_angle: f32, end_angle: f32, anticlockwise: bool) {
+    self.ops.push(PathOp::Ellipse(Point2D::new(x, y), radius_x, radius_y, rotation,
+                                  start_angle, end_angle, anticlockwise));
+  }
+
+  pub fn rect(&mut self, x: f32, y: f32, width: f32, height: f32) {
+    self.ops.push(PathOp::Rect(Rect::new(Point2D::new(x, y), Size2D::new(width, height))));
+  }
+
+  pub fn close_path(&mut self) {
+    self.ops.push(PathOp::ClosePath);
+  }
+
+  // https://html.spec.whatwg.org/multipage/#dom-path2d-addpath
+  pub fn add_path(&mut self, path: &Path2D, transform: Option<Transform2D<f32>>) {
+    match transform {
+      None => self.ops.extend_from_slice(&path.ops),
+      Some(ref transform) => {
+        let ops = path.transformed_ops(transform);
+        self.ops.extend(ops);
+      },
+    }
+  }
+
+  /// Maps every op through `transform`. Curved ops can't be expressed with their
+  /// own parameters under a skew or a non uniform scale, so they are flattened to bezier curves.
+  fn transformed_ops(&self, transform: &Transform2D<f32>) -> Vec<PathOp> {
+    let t = |p: &Point2D<f32>| transform.transform_point(p);
+    let mut ops = vec![];
+    let mut current_point: Option<Point2D<f32>> = None;
+    let mut subpath_start = Point2D::zero();
+    for op in self.ops.iter() {
+      match *op {
+        PathOp::MoveTo(ref p) => {
+          ops.push(PathOp::MoveTo(t(p)));
+          current_point = Some(*p);
+          subpath_start = *p;
+        },
+        PathOp::LineTo(ref p) => {
+          ops.push(PathOp::LineTo(t(p)));
+          current_point = Some(*p);
+        },
+        PathOp::QuadraticCurveTo(ref cp, ref p) => {
+          ops.push(PathOp::QuadraticCurveTo(t(cp), t(p)));
+          current_point = Some(*p);
+        },
+        PathOp::BezierCurveTo(ref cp1, ref cp2, ref p) => {
+          ops.push(PathOp::BezierCurveTo(t(cp1), t(cp2), t(p)));
+          current_point = Some(*p);
+        },
+        PathOp::Rect(ref rect) => {
+          ops.push(PathOp::MoveTo(t(&rect.origin)));
+          ops.push(PathOp::LineTo(t(&rect.top_right())));
+          ops.push(PathOp::LineTo(t(&rect.bottom_right())));
+          ops.push(PathOp::LineTo(t(&rect.bottom_left())));
+          ops.push(PathOp::ClosePath);
+          current_point = Some(rect.origin);
+          subpath_start = rect.origin;
+        },
+        PathOp::ClosePath => {
+          ops.push(PathOp::ClosePath);
+          current_point = Some(subpath_start);
+        },
+        PathOp::Arc(ref center, radius, start, end, ccw) => {
+          let end_point = ellipse_to_beziers(&mut ops, current_point, transform,
+                                             center, radius, radius, 0.0, start, end, ccw);
+          current_point = Some(end_point);
+        },
+        PathOp::Ellipse(ref center, radius_x, radius_y, rotation, start, end, ccw) => {
+          let end_point = ellipse_to_beziers(&mut ops, current_point, transform,
+                                             center, radius_x, radius_y, rotation, start, end, ccw);
+          current_point = Some(end_point);
+        },
+        PathOp::ArcTo(ref cp1, ref cp2, radius) => {
+          let cp0 = match current_point {
+            Some(p) => p,
+            None => {
+              ops.push(PathOp::MoveTo(t(cp1)));
+              current_point = Some(*cp1);
+              subpath_start = *cp1;
+              continue;
+            },
+          };
+          let (tangent_point, arc) = arc_to_segment(&cp0, cp1, cp2, radius);
+          ops.push(PathOp::LineTo(t(&tangent_point)));
+          current_point = Some(tangent_point);
+          if let Some(ArcSegment { center, radius, start_angle, end_angle, anticlockwise }) = arc {
+            let end_point = ellipse_to_beziers(&mut ops, current_point, transform, &center,
+                                               radius, radius, 0.0, start_angle, end_angle, anticlockwise);
+            current_point = Some(end_point);
+          }
+        },
+      }
+    }
+    ops
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ArcSegment {
+  pub center: Point2D<f32>,
+  pub radius: f32,
+  pub start_angle: f32,
+  pub end_angle: f32,
+  pub anticlockwise: bool,
+}
+
+/// Resolves `arcTo` from the current point `cp0`, returns the point to draw a line to
+/// and the arc that follows it, if any.
+// https://html.spec.whatwg.org/multipage/#dom-context-2d-arcto
+pub fn arc_to_segment(cp0: &Point2D<f32>, cp1: &Point2D<f32>, cp2: &Point2D<f32>,
+                      radius: f32) -> (Point2D<f32>, Option<ArcSegment>) {
+  let cp0 = *cp0;
+  let cp1 = *cp1;
+  let cp2 = *cp2;
+
+  if (cp0.x == cp1.x && cp0.y == cp1.y) || cp1 == cp2 || radius == 0.0 {
+    return (cp1, None);
+  }
+
+  // if all three control points lie on a single straight line,
+  // connect the first two by a straight line
+  let direction = (cp2.x - cp1.x) * (cp0.y - cp1.y) + (cp2.y - cp1.y) * (cp1.x - cp0.x);
+  if direction == 0.0 {
+    return (cp1, None);
+  }
+
+  // otherwise, draw the Arc
+  let a2 = (cp0.x - cp1.x).powi(2) + (cp0.y - cp1.y).powi(2);
+  let b2 = (cp1.x - cp2.x).powi(2) + (cp1.y - cp2.y).powi(2);
+  let d = {
+    let c2 = (cp0.x - cp2.x).powi(2) + (cp0.y - cp2.y).powi(2);
+    let cosx = (a2 + b2 - c2) / (2.0 * (a2 * b2).sqrt());
+    let sinx = (1.0 - cosx.powi(2)).sqrt();
+    radius / ((1.0 - cosx) / sinx)
+  };
+
+  // first tangent point
+  let anx = (cp1.x - cp0.x) / a2.sqrt();
+  let any = (cp1.y - cp0.y) / a2.sqrt();
+  let tp1 = Point2D::new(cp1.x - anx * d, cp1.y - any * d);
+
+  // second tangent point
+  let bnx = (cp1.x - cp2.x) / b2.sqrt();
+  let bny = (cp1.y - cp2.y) / b2.sqrt();
+  let tp2 = Point2D::new(cp1.x - bnx * d, cp1.y - bny * d);
+
+  // arc center and angles
+  let anticlockwise = direction < 0.0;
+  let cx = tp1.x + any * radius * if anticlockwise { 1.0 } else { -1.0 };
+  let cy = tp1.y - anx * radius * if anticlockwise { 1.0 } else { -1.0 };
+  let start_angle = (tp1.y - cy).atan2(tp1.x - cx);
+  let end_angle = (tp2.y - cy).atan2(tp2.x - cx);
+
+  if [cx, cy, start_angle, end_angle].iter().all(|x| x.is_finite()) {
+    (tp1, Some(ArcSegment { center: Point2D::new(cx, cy), radius, start_angle, end_angle, anticlockwise }))
+  } else {
+    (tp1, None)
+  }
+}
+
+/// The signed angle swept from `start` to `end`, clamped to one turn.
+// https://html.spec.whatwg.org/multipage/#dom-context-2d-ellipse
+fn arc_sweep(start: f32, end: f32, anticlockwise: bool) -> f32 {
+  let two_pi = 2.0 * PI;
+  let delta = end - start;
+  if !anticlockwise {
+    if delta >= two_pi {
+      two_pi
+    } else {
+      let d = delta % two_pi;
+      if d < 0.0 { d + two_pi } else { d }
+    }
+  } else {
+    if -delta >= two_pi {
+      -two_pi
+    } else {
+      let d = delta % two_pi;
+      if d > 0.0 { d - two_pi } else { d }
+    }
+  }
+}
+
+/// Appends the ellipse as cubic bezier curves mapped through `transform`,
+/// returns the untransformed end point.
+fn ellipse_to_beziers(ops: &mut Vec<PathOp>, current_point: Option<Point2D<f32>>,
+                      transform: &Transform2D<f32>, center: &Point2D<f32>,
+                      radius_x: f32, radius_y: f32, rotation: f32,
+                      start: f32, end: f32, anticlockwise: bool) -> Point2D<f32> {
+  let (sin_rotation, cos_rotation) = rotation.sin_cos();
+  let point_at = |ux: f32, uy: f32| -> Point2D<f32> {
+    let x = ux * radius_x;
+    let y = uy * radius_y;
+    Point2D::new(center.x + x * cos_rotation - y * sin_rotation,
+                 center.y + x * sin_rotation + y * cos_rotation)
+  };
+
+  let start_point = point_at(start.cos(), start.sin());
+  ops.push(match current_point {
+    Some(_) => PathOp::LineTo(transform.transform_point(&start_point)),
+    None => PathOp::MoveTo(transform.transform_point(&start_point)),
+  });
+
+  let sweep = arc_sweep(start, end, anticlockwise);
+  let segments = (sweep.abs() / (PI / 2.0)).ceil().max(1.0) as usize;
+  let step = sweep / segments as f32;
+  let k = 4.0 / 3.0 * (step / 4.0).tan();
+  let mut angle = start;
+  let mut end_point = start_point;
+  for _ in 0..segments {
+    let next = angle + step;
+    let (sin_a, cos_a) = angle.sin_cos();
+    let (sin_b, cos_b) = next.sin_cos();
+    let cp1 = point_at(cos_a - k * sin_a, sin_a + k * cos_a);
+    let cp2 = point_at(cos_b + k * sin_b, sin_b - k * cos_b);
+    end_point = point_at(cos_b, sin_b);
+    ops.push(PathOp::BezierCurveTo(transform.transform_point(&cp1),
+                                   transform.transform_point(&cp2),
+                                   transform.transform_point(&end_point)));
+    angle = next;
+  }
+  end_point
+}
+
+#[cfg(test)]
+mod path2d_tests {
+  use super::*;
+
+  fn assert_point_eq(a: &Point2D<f32>, b: &Point2D<f32>) {
+    assert!((a.x - b.x).abs() < 1e-3 && (a.y - b.y).abs() < 1e-3, "{:?} != {:?}", a, b);
+  }
+
+  #[test]
+  fn should_add_path_without_transform() {
+    let mut path = Path2D::new();
+    path.move_to(1.0, 2.0);
+    path.arc(10.0, 10.0, 5.0, 0.0, PI, false);
+    let mut result = Path2D::new();
+    result.add_path(&path, None);
+    assert_eq!(result, path);
+  }
+
+  #[test]
+  fn should_add_path_with_transform() {
+    let mut path = Path2D::new();
+    path.rect(0.0, 0.0, 10.0, 10.0);
+    let mut result = Path2D::new();
+    result.add_path(&path, Some(Transform2D::create_translation(5.0, 5.0).post_scale(2.0, 2.0)));
+    assert_eq!(result.ops()[0], PathOp::MoveTo(Point2D::new(10.0, 10.0)));
+    assert_eq!(result.ops()[2], PathOp::LineTo(Point2D::new(30.0, 30.0)));
+    assert_eq!(result.ops()[4], PathOp::ClosePath);
+  }
+
+  #[test]
+  fn should_flatten_transformed_arc() {
+    let mut path = Path2D::new();
+    path.arc(0.0, 0.0, 10.0, 0.0, PI, false);
+    let mut result = Path2D::new();
+    result.add_path(&path, Some(Transform2D::create_scale(2.0, 1.0)));
+    assert_eq!(result.ops()[0], PathOp::MoveTo(Point2D::new(20.0, 0.0)));
+    // half a turn is split into two quarters
+    assert_eq!(result.ops().len(), 3);
+    match result.ops()[2] {
+      PathOp::BezierCurveTo(_, _, ref end) => assert_point_eq(end, &Point2D::new(-20.0, 0.0)),
+      ref op => panic!("unexpected op: {:?}", op),
+    };
+  }
+
+  #[test]
+  fn should_compute_arc_sweep() {
+    assert_eq!(arc_sweep(0.0, 3.0 * PI, false), 2.0 * PI);
+    assert!((arc_sweep(0.0, -PI / 2.0, false) - 1.5 * PI).abs() < 1e-5);
+    assert!((arc_sweep(0.0, PI / 2.0, true) + 1.5 * PI).abs() < 1e-5);
+  }
+
+  #[test]
+  fn should_resolve_arc_to_on_a_straight_line() {
+    let (point, arc) = arc_to_segment(&Point2D::new(0.0, 0.0), &Point2D::new(10.0, 0.0),
+                                      &Point2D::new(20.0, 0.0), 5.0);
+    assert_eq!(point, Point2D::new(10.0, 0.0));
+    assert!(arc.is_none());
+  }
+
+  #[test]
+  fn should_resolve_arc_to_corner() {
+    let (point, arc) = arc_to_segment(&Point2D::new(0.0, 0.0), &Point2D::new(10.0, 0.0),
+                                      &Point2D::new(10.0, 10.0), 5.0);
+    assert_point_eq(&point, &Point2D::new(5.0, 0.0));
+    assert_point_eq(&arc.unwrap().center, &Point2D::new(5.0, 5.0));
+  }
+}
@@ -1,7 +1,8 @@
-use cssparser::{Parser, ParserInput, Token};
+use cssparser::{Color, ParseError, Parser, ParserInput, RGBA, Token};
+use euclid::{Vector2D};
 use std::ops::{Deref};
 
-use super::canvas::{Font, FontStyle, FontVariant};
+use super::canvas::{CanvasFilter, FilterFunction, Font, FontStretch, FontStyle, FontVariant};
 
 #[cfg(target_os = "linux")]
 pub static SANS_SERIF_FONT_FAMILY: &'static str = "DejaVu Sans";
@@ -14,82 +15,406 @@ pub static CURSIVE_FONT_FAMILY: &'static str = "Apple Chancery";
 pub static FANTASY_FONT_FAMILY: &'static str = "Papyrus";
 pub static MONOSPACE_FONT_FAMILY: &'static str = "Menlo";
 
-pub fn parse_fonts_style(input: &str) -> Font {
+/// `medium`, the size `em`, `rem` and percentages are relative to.
+const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+// https://drafts.csswg.org/css-fonts/#font-prop
+/// Parses the css `font` shorthand, line-height is validated but dropped
+/// since canvas always uses `normal`.
+pub fn parse_fonts_style(input: &str) -> Result<Font, ()> {
   let parser_input = &mut ParserInput::new(input);
   let mut css_parser = Parser::new(parser_input);
-  let mut font_size = 10.0;
-  let mut font_family = "san-serif".to_string();
-  let mut index = 0;
-  let mut font_variant = FontVariant::Normal;
-  let mut font = None;
-  let mut font_style: FontStyle = FontStyle::Normal;
-  while !css_parser.is_exhausted() {
-    match css_parser.next() {
-      Ok(t) => {
-        match t {
-          &Token::Dimension { ref value, ref unit, .. } => {
-            let s = unit.deref().to_lowercase();
-            // handle others absolute unit here
-            if s == "px" {
-              font_size = * value;
-            } else if s == "em" {
-              font_size = value * (16 as f32);
-            };
-          },
-          &Token::QuotedString(ref d) => {
-            font_family = String::from(d.deref());
-          },
-          &Token::Ident(ref d) => {
-            let val = d.deref().to_lowercase();
-            if index == 0 || index == 1 {
-              if val == "small-caps" {
-                font_variant = FontVariant::SmallCaps;
-              }
-              if index == 0 {
-                if val == "italic" {
-                  font_style = FontStyle::Italic;
-                } else if val == "oblique" {
-                  font_style = FontStyle::Oblique;
-                } else if val != "normal" {
-                  println!("font style: {} is illegal", val);
-                }
-              }
-            } else {
-              font = Some(String::from(val));
-            }
-          },
-          _ => println!("other branch: {:?}", t),
-        };
-        index = index + 1;
+  let mut tokens = vec![];
+  while let Ok(token) = css_parser.next() {
+    tokens.push(token.clone());
+  }
+  if !css_parser.is_exhausted() {
+    return Err(());
+  }
+  let mut tokens = tokens.iter().peekable();
 
+  let mut font_style = None;
+  let mut font_variant = None;
+  let mut font_weight = None;
+  let mut font_stretch = None;
+  let mut prefix_count = 0;
+  // style, variant, weight and stretch come in any order before the size
+  let font_size = loop {
+    let token = tokens.next().ok_or(())?;
+    if let Some(size) = parse_font_size(token)? {
+      break size;
+    }
+    prefix_count += 1;
+    if prefix_count > 4 {
+      return Err(());
+    }
+    match *token {
+      Token::Ident(ref ident) => {
+        let ident = ident.to_lowercase();
+        if ident == "normal" {
+          continue;
+        }
+        if let Some(style) = parse_font_style(&ident) {
+          set_once(&mut font_style, style)?;
+        } else if ident == "small-caps" {
+          set_once(&mut font_variant, FontVariant::SmallCaps)?;
+        } else if let Some(weight) = parse_font_weight_keyword(&ident) {
+          set_once(&mut font_weight, weight)?;
+        } else if let Some(stretch) = parse_font_stretch(&ident) {
+          set_once(&mut font_stretch, stretch)?;
+        } else {
+          return Err(());
+        }
       },
-      Err(_) => {
-        font_family = font.unwrap();
-        font = None;
+      Token::Number { value, .. } if value >= 1.0 && value <= 1000.0 => {
+        set_once(&mut font_weight, value.round() as u16)?;
       },
-    };
+      _ => return Err(()),
+    }
+  };
+
+  if let Some(&&Token::Delim('/')) = tokens.peek() {
+    tokens.next();
+    match tokens.next() {
+      Some(&Token::Ident(ref ident)) if ident.eq_ignore_ascii_case("normal") => { },
+      Some(&Token::Number { value, .. }) if value >= 0.0 => { },
+      Some(&Token::Percentage { unit_value, .. }) if unit_value >= 0.0 => { },
+      Some(&Token::Dimension { value, ref unit, .. }) if value >= 0.0 && length_unit(unit).is_some() => { },
+      _ => return Err(()),
+    }
+  }
+
+  let mut families = vec![];
+  let mut words: Vec<String> = vec![];
+  let mut quoted = None;
+  loop {
+    match tokens.next() {
+      Some(&Token::QuotedString(ref family)) if words.is_empty() && quoted.is_none() => {
+        quoted = Some(family.deref().to_string());
+      },
+      Some(&Token::Ident(ref word)) if quoted.is_none() => {
+        words.push(word.deref().to_string());
+      },
+      separator @ Some(&Token::Comma) | separator @ None => {
+        let family = match quoted.take() {
+          Some(family) => family,
+          None if words.len() == 1 => resolve_generic_family(&words[0])?,
+          None if words.len() > 1 => words.join(" "),
+          None => return Err(()),
+        };
+        words.clear();
+        families.push(family);
+        if separator.is_none() {
+          break;
+        }
+      },
+      _ => return Err(()),
+    }
+  }
+
+  let font_family = families.remove(0);
+  Ok(Font {
+    font_size,
+    font_style: font_style.unwrap_or(FontStyle::Normal),
+    font_weight: font_weight.unwrap_or(400),
+    font_stretch: font_stretch.unwrap_or(FontStretch::Normal),
+    font_family,
+    fallback_families: families,
+    font_variant: font_variant.unwrap_or(FontVariant::Normal),
+  })
+}
+
+// https://html.spec.whatwg.org/multipage/#dom-context-2d-fillstyle
+/// Parses a css color, `currentColor` is black as there is no element to inherit from.
+pub fn parse_color(input: &str) -> Result<RGBA, ()> {
+  let parser_input = &mut ParserInput::new(input);
+  let mut css_parser = Parser::new(parser_input);
+  let color = match Color::parse(&mut css_parser) {
+    Ok(Color::RGBA(rgba)) => rgba,
+    Ok(Color::CurrentColor) => RGBA::new(0, 0, 0, 255),
+    // cssparser doesn't know hwb() yet
+    Err(_) => return parse_hwb(input),
+  };
+  if !css_parser.is_exhausted() {
+    return Err(());
+  }
+  Ok(color)
+}
+
+// https://drafts.csswg.org/css-color-4/#the-hwb-notation
+fn parse_hwb(input: &str) -> Result<RGBA, ()> {
+  let parser_input = &mut ParserInput::new(input);
+  let mut css_parser = Parser::new(parser_input);
+  css_parser.expect_function_matching("hwb").map_err(|_| ())?;
+  let tokens = css_parser.parse_nested_block(|parser| -> Result<Vec<Token>, ParseError<()>> {
+    let mut tokens = vec![];
+    while let Ok(token) = parser.next() {
+      tokens.push(token.clone());
+    }
+    Ok(tokens)
+  }).map_err(|_| ())?;
+  if !css_parser.is_exhausted() {
+    return Err(());
+  }
+
+  let hue = match tokens.get(0) {
+    Some(&Token::Number { value, .. }) => value,
+    Some(&Token::Dimension { value, ref unit, .. }) => value * angle_unit(unit).ok_or(())?,
+    _ => return Err(()),
+  };
+  let percentage = |token: Option<&Token>| match token {
+    Some(&Token::Percentage { unit_value, .. }) => Ok(unit_value.max(0.0).min(1.0)),
+    _ => Err(()),
   };
+  let whiteness = percentage(tokens.get(1))?;
+  let blackness = percentage(tokens.get(2))?;
+  let alpha = match (tokens.len(), tokens.get(3), tokens.get(4)) {
+    (3, _, _) => 1.0,
+    (5, Some(&Token::Delim('/')), Some(&Token::Number { value, .. })) => value,
+    (5, Some(&Token::Delim('/')), Some(&Token::Percentage { unit_value, .. })) => unit_value,
+    _ => return Err(()),
+  };
+
+  let channel = |n: f32| {
+    if whiteness + blackness >= 1.0 {
+      return whiteness / (whiteness + blackness);
+    }
+    // the fully saturated hue, then mixed with white and black
+    let k = (n + hue / 30.0) % 12.0;
+    let k = if k < 0.0 { k + 12.0 } else { k };
+    let pure = 0.5 - 0.5 * (k - 3.0).min(9.0 - k).min(1.0).max(-1.0);
+    pure * (1.0 - whiteness - blackness) + whiteness
+  };
+  Ok(RGBA::from_floats(channel(0.0), channel(8.0), channel(4.0), alpha.max(0.0).min(1.0)))
+}
+
+/// Degrees per unit.
+fn angle_unit(unit: &str) -> Option<f32> {
+  match unit.to_lowercase().as_str() {
+    "deg" => Some(1.0),
+    "grad" => Some(0.9),
+    "rad" => Some(180.0 / ::std::f32::consts::PI),
+    "turn" => Some(360.0),
+    _ => None,
+  }
+}
+
+// https://drafts.fxtf.org/filter-effects/#FilterProperty
+/// Parses a css `filter`, `none` is the empty chain. `url()` references are
+/// rejected as there is no document to look them up in.
+pub fn parse_filter(input: &str) -> Result<CanvasFilter, ()> {
+  let parser_input = &mut ParserInput::new(input);
+  let mut css_parser = Parser::new(parser_input);
+  if css_parser.try(|parser| parser.expect_ident_matching("none")).is_ok() {
+    return if css_parser.is_exhausted() { Ok(CanvasFilter::default()) } else { Err(()) };
+  }
+
+  let mut functions = vec![];
+  while !css_parser.is_exhausted() {
+    let name = css_parser.expect_function().map_err(|_| ())?.to_lowercase();
+    let mut colors = vec![];
+    let mut tokens: Vec<Token> = vec![];
+    css_parser.parse_nested_block(|parser| -> Result<(), ParseError<()>> {
+      loop {
+        // only drop-shadow takes a color, which may be a function itself
+        if let Ok(color) = parser.try(|parser| Color::parse(parser)) {
+          colors.push(color);
+          continue;
+        }
+        match parser.next() {
+          Ok(token) => tokens.push(token.clone()),
+          Err(_) => break,
+        }
+      }
+      Ok(())
+    }).map_err(|_| ())?;
+    functions.push(parse_filter_function(&name, &tokens, &colors)?);
+  }
+  if functions.is_empty() {
+    return Err(());
+  }
+  Ok(CanvasFilter { functions })
+}
+
+fn parse_filter_function(name: &str, tokens: &[Token], colors: &[Color]) -> Result<FilterFunction, ()> {
+  if name == "drop-shadow" {
+    return parse_drop_shadow(tokens, colors);
+  }
+  if !colors.is_empty() || tokens.len() > 1 {
+    return Err(());
+  }
+  match name {
+    "blur" => {
+      let blur = match tokens.get(0) {
+        Some(token) => parse_length(token)?,
+        None => 0.0,
+      };
+      if blur < 0.0 {
+        return Err(());
+      }
+      return Ok(FilterFunction::Blur(blur));
+    },
+    "hue-rotate" => {
+      let angle = match tokens.get(0) {
+        Some(&Token::Dimension { value, ref unit, .. }) => value * angle_unit(unit).ok_or(())?,
+        Some(&Token::Number { value, .. }) if value == 0.0 => 0.0,
+        None => 0.0,
+        _ => return Err(()),
+      };
+      return Ok(FilterFunction::HueRotate(angle));
+    },
+    _ => {},
+  }
+
+  let amount = match tokens.get(0) {
+    Some(&Token::Number { value, .. }) => value,
+    Some(&Token::Percentage { unit_value, .. }) => unit_value,
+    None => 1.0,
+    _ => return Err(()),
+  };
+  if amount < 0.0 || !amount.is_finite() {
+    return Err(());
+  }
+  // amounts past the flat result are clamped
+  let clamped = amount.min(1.0);
+  match name {
+    "brightness" => Ok(FilterFunction::Brightness(amount)),
+    "contrast" => Ok(FilterFunction::Contrast(amount)),
+    "grayscale" => Ok(FilterFunction::Grayscale(clamped)),
+    "invert" => Ok(FilterFunction::Invert(clamped)),
+    "opacity" => Ok(FilterFunction::Opacity(clamped)),
+    "saturate" => Ok(FilterFunction::Saturate(amount)),
+    "sepia" => Ok(FilterFunction::Sepia(clamped)),
+    _ => Err(()),
+  }
+}
+
+/// Two offsets and an optional blur radius, the color may come first or last
+/// and defaults to `currentColor`, black on a canvas.
+fn parse_drop_shadow(tokens: &[Token], colors: &[Color]) -> Result<FilterFunction, ()> {
+  let color = match colors.get(0) {
+    _ if colors.len() > 1 => return Err(()),
+    Some(&Color::RGBA(rgba)) => rgba,
+    _ => RGBA::new(0, 0, 0, 255),
+  };
+  let lengths = tokens.iter().map(parse_length).collect::<Result<Vec<f32>, ()>>()?;
+  let blur = match lengths.len() {
+    2 => 0.0,
+    3 if lengths[2] >= 0.0 => lengths[2],
+    _ => return Err(()),
+  };
+  Ok(FilterFunction::DropShadow {
+    offset: Vector2D::new(lengths[0], lengths[1]),
+    blur: blur / 2.0,
+    color,
+  })
+}
 
-  match font {
-    Some(s) => font_family = String::from(s),
-    None => { },
+fn parse_length(token: &Token) -> Result<f32, ()> {
+  match *token {
+    Token::Dimension { value, ref unit, .. } => length_unit(unit).map(|px| value * px).ok_or(()),
+    Token::Number { value, .. } if value == 0.0 => Ok(0.0),
+    _ => Err(()),
+  }
+}
+
+fn set_once<T>(slot: &mut Option<T>, value: T) -> Result<(), ()> {
+  if slot.is_some() {
+    return Err(());
+  }
+  *slot = Some(value);
+  Ok(())
+}
+
+/// `Ok(None)` when the token is not a size, negative sizes are errors.
+fn parse_font_size(token: &Token) -> Result<Option<f32>, ()> {
+  let size = match *token {
+    Token::Dimension { value, ref unit, .. } => match length_unit(unit) {
+      Some(px) => value * px,
+      None => return Err(()),
+    },
+    Token::Percentage { unit_value, .. } => unit_value * DEFAULT_FONT_SIZE,
+    Token::Number { value, .. } if value == 0.0 => 0.0,
+    Token::Ident(ref ident) => match ident.to_lowercase().as_str() {
+      "xx-small" => 9.0,
+      "x-small" => 10.0,
+      "small" => 13.0,
+      "medium" => 16.0,
+      "large" => 18.0,
+      "x-large" => 24.0,
+      "xx-large" => 32.0,
+      "xxx-large" => 48.0,
+      "larger" => DEFAULT_FONT_SIZE * 1.2,
+      "smaller" => DEFAULT_FONT_SIZE / 1.2,
+      _ => return Ok(None),
+    },
+    _ => return Ok(None),
   };
+  if size < 0.0 || !size.is_finite() {
+    return Err(());
+  }
+  Ok(Some(size))
+}
+
+/// Pixels per unit.
+fn length_unit(unit: &str) -> Option<f32> {
+  match unit.to_lowercase().as_str() {
+    "px" => Some(1.0),
+    "pt" => Some(96.0 / 72.0),
+    "pc" => Some(16.0),
+    "in" => Some(96.0),
+    "cm" => Some(96.0 / 2.54),
+    "mm" => Some(96.0 / 25.4),
+    "q" => Some(96.0 / 101.6),
+    "em" | "rem" => Some(DEFAULT_FONT_SIZE),
+    "ex" | "ch" => Some(DEFAULT_FONT_SIZE / 2.0),
+    _ => None,
+  }
+}
+
+fn parse_font_style(ident: &str) -> Option<FontStyle> {
+  match ident {
+    "italic" => Some(FontStyle::Italic),
+    "oblique" => Some(FontStyle::Oblique),
+    _ => None,
+  }
+}
+
+/// `bolder` and `lighter` are relative to the `normal` weight of the canvas element.
+fn parse_font_weight_keyword(ident: &str) -> Option<u16> {
+  match ident {
+    "bold" | "bolder" => Some(700),
+    "lighter" => Some(100),
+    _ => None,
+  }
+}
+
+fn parse_font_stretch(ident: &str) -> Option<FontStretch> {
+  match ident {
+    "ultra-condensed" => Some(FontStretch::UltraCondensed),
+    "extra-condensed" => Some(FontStretch::ExtraCondensed),
+    "condensed" => Some(FontStretch::Condensed),
+    "semi-condensed" => Some(FontStretch::SemiCondensed),
+    "semi-expanded" => Some(FontStretch::SemiExpanded),
+    "expanded" => Some(FontStretch::Expanded),
+    "extra-expanded" => Some(FontStretch::ExtraExpanded),
+    "ultra-expanded" => Some(FontStretch::UltraExpanded),
+    _ => None,
+  }
+}
 
-  let font_family = match font_family.as_str() {
+fn resolve_generic_family(family: &str) -> Result<String, ()> {
+  let family = match family.to_lowercase().as_str() {
     "serif" => SERIF_FONT_FAMILY,
     "sans-serif" => SANS_SERIF_FONT_FAMILY,
     "cursive" => CURSIVE_FONT_FAMILY,
     "fantasy" => FANTASY_FONT_FAMILY,
     "monospace" => MONOSPACE_FONT_FAMILY,
-    other => other,
+    // css wide keywords can't be a family name
+    "inherit" | "initial" | "unset" | "default" => return Err(()),
+    _ => family,
   };
-
-  Font {
-    font_size,
-    font_style,
-    font_family: font_family.to_string(),
-    font_variant,
-  }
+  Ok(family.to_string())
 }
 
 #[cfg(test)]
@@ -97,7 +422,7 @@ mod parse_fonts_style_tests {
   use super::*;
   #[test]
   fn should_parse_size_family() {
-    let result = parse_fonts_style("2em \"Open Sans\"");
+    let result = parse_fonts_style("2em \"Open Sans\"").unwrap();
     assert_eq!(result.font_family, String::from("Open Sans"));
     assert_eq!(result.font_size, 32 as f32);
     assert_eq!(result.font_variant, FontVariant::Normal);
@@ -105,7 +430,7 @@ mod parse_fonts_style_tests {
 
   #[test]
   fn should_parse_style_size_family() {
-    let result = parse_fonts_style("italic 2em \"Open Sans\"");
+    let result = parse_fonts_style("italic 2em \"Open Sans\"").unwrap();
     assert_eq!(result.font_family, String::from("Open Sans"));
     assert_eq!(result.font_size, 32 as f32);
     assert_eq!(result.font_style, FontStyle::Italic);
@@ -114,9 +439,124 @@ mod parse_fonts_style_tests {
 
   #[test]
   fn should_parse_style_variant_weight_size_lineheight_family() {
-    let result = parse_fonts_style("italic small-caps bolder 16px/3 cursive");
+    let result = parse_fonts_style("italic small-caps bolder 16px/3 cursive").unwrap();
     assert_eq!(result.font_family, String::from(CURSIVE_FONT_FAMILY));
     assert_eq!(result.font_size, 16 as f32);
     assert_eq!(result.font_variant, FontVariant::SmallCaps);
+    assert_eq!(result.font_weight, 700);
+  }
+
+  #[test]
+  fn should_parse_weight_stretch_and_family_list() {
+    let result = parse_fonts_style("condensed 300 normal 12pt/1.5em Open Sans, 'Noto Sans', serif").unwrap();
+    assert_eq!(result.font_weight, 300);
+    assert_eq!(result.font_stretch, FontStretch::Condensed);
+    assert_eq!(result.font_size, 16.0);
+    assert_eq!(result.font_family, "Open Sans");
+    assert_eq!(result.fallback_families, vec!["Noto Sans".to_string(), SERIF_FONT_FAMILY.to_string()]);
+  }
+
+  #[test]
+  fn should_parse_size_units_and_keywords() {
+    assert_eq!(parse_fonts_style("1in a").unwrap().font_size, 96.0);
+    assert_eq!(parse_fonts_style("2pc a").unwrap().font_size, 32.0);
+    assert_eq!(parse_fonts_style("50% a").unwrap().font_size, 8.0);
+    assert_eq!(parse_fonts_style("x-large a").unwrap().font_size, 24.0);
+    assert_eq!(parse_fonts_style("12.5px a").unwrap().font_size, 12.5);
+  }
+
+  #[test]
+  fn should_reject_invalid_fonts() {
+    assert!(parse_fonts_style("").is_err());
+    assert!(parse_fonts_style("10px").is_err());
+    assert!(parse_fonts_style("sans-serif").is_err());
+    assert!(parse_fonts_style("bold bold 10px a").is_err());
+    assert!(parse_fonts_style("normal normal normal normal normal 10px a").is_err());
+    assert!(parse_fonts_style("-1px a").is_err());
+    assert!(parse_fonts_style("10px inherit").is_err());
+    assert!(parse_fonts_style("10px a,").is_err());
+    assert!(parse_fonts_style("10px 'a' b").is_err());
+    assert!(parse_fonts_style("10foo a").is_err());
+  }
+}
+
+#[cfg(test)]
+mod parse_color_tests {
+  use super::*;
+
+  #[test]
+  fn should_parse_css_colors() {
+    assert_eq!(parse_color("#f00"), Ok(RGBA::new(255, 0, 0, 255)));
+    assert_eq!(parse_color(" #00ff0080 "), Ok(RGBA::new(0, 255, 0, 128)));
+    assert_eq!(parse_color("rgba(0, 0, 255, 0.5)"), Ok(RGBA::new(0, 0, 255, 128)));
+    assert_eq!(parse_color("hsl(120, 100%, 25%)"), Ok(RGBA::new(0, 128, 0, 255)));
+    assert_eq!(parse_color("RebeccaPurple"), Ok(RGBA::new(102, 51, 153, 255)));
+    assert_eq!(parse_color("transparent"), Ok(RGBA::new(0, 0, 0, 0)));
+    assert_eq!(parse_color("currentColor"), Ok(RGBA::new(0, 0, 0, 255)));
+  }
+
+  #[test]
+  fn should_parse_hwb() {
+    assert_eq!(parse_color("hwb(0 0% 0%)"), Ok(RGBA::new(255, 0, 0, 255)));
+    assert_eq!(parse_color("hwb(0.5turn 0% 50% / 50%)"), Ok(RGBA::new(0, 128, 128, 128)));
+    assert_eq!(parse_color("hwb(90 60% 60%)"), Ok(RGBA::new(128, 128, 128, 255)));
+  }
+
+  #[test]
+  fn should_reject_invalid_colors() {
+    assert!(parse_color("").is_err());
+    assert!(parse_color("red blue").is_err());
+    assert!(parse_color("#ff").is_err());
+    assert!(parse_color("hwb(0, 0%, 0%)").is_err());
+    assert!(parse_color("hwb(0 0% 0%) red").is_err());
+  }
+}
+
+#[cfg(test)]
+mod parse_filter_tests {
+  use super::*;
+
+  #[test]
+  fn should_parse_filter_chain() {
+    let filter = parse_filter("blur(2px) grayscale(50%) hue-rotate(0.5turn) opacity(2)").unwrap();
+    assert_eq!(filter.functions, vec![
+      FilterFunction::Blur(2.0),
+      FilterFunction::Grayscale(0.5),
+      FilterFunction::HueRotate(180.0),
+      FilterFunction::Opacity(1.0),
+    ]);
+    assert_eq!(parse_filter("brightness() contrast(150%)").unwrap().functions,
+               vec![FilterFunction::Brightness(1.0), FilterFunction::Contrast(1.5)]);
+    assert!(parse_filter(" none ").unwrap().is_none());
+  }
+
+  #[test]
+  fn should_parse_drop_shadow() {
+    let shadow = FilterFunction::DropShadow {
+      offset: Vector2D::new(2.0, -3.0),
+      blur: 2.0,
+      color: RGBA::new(255, 0, 0, 128),
+    };
+    assert_eq!(parse_filter("drop-shadow(2px -3px 4px rgba(255, 0, 0, 0.5))").unwrap().functions, vec![shadow.clone()]);
+    assert_eq!(parse_filter("drop-shadow(rgba(255, 0, 0, 0.5) 2px -3px 4px)").unwrap().functions, vec![shadow]);
+    assert_eq!(parse_filter("drop-shadow(1px 1px)").unwrap().functions, vec![FilterFunction::DropShadow {
+      offset: Vector2D::new(1.0, 1.0),
+      blur: 0.0,
+      color: RGBA::new(0, 0, 0, 255),
+    }]);
+  }
+
+  #[test]
+  fn should_reject_invalid_filters() {
+    assert!(parse_filter("").is_err());
+    assert!(parse_filter("none blur(1px)").is_err());
+    assert!(parse_filter("blur(-1px)").is_err());
+    assert!(parse_filter("blur(50%)").is_err());
+    assert!(parse_filter("sepia(-1)").is_err());
+    assert!(parse_filter("invert(1, 2)").is_err());
+    assert!(parse_filter("url(#filter)").is_err());
+    assert!(parse_filter("drop-shadow(1px)").is_err());
+    assert!(parse_filter("drop-shadow(1px 1px -1px)").is_err());
+    assert!(parse_filter("grayscale(1) sparkle(1)").is_err());
   }
 }
@@ -0,0 +1,42 @@
+use std::ops::{Range};
+
+use unicode_bidi::{BidiInfo, Level};
+
+/// Splits a line into directional runs in visual order, left to right.
+/// Each run is a byte range of `text` and whether it is right to left.
+pub fn visual_runs(text: &str, rtl: bool) -> Vec<(Range<usize>, bool)> {
+  if text.is_empty() {
+    return vec![];
+  }
+  let level = if rtl { Level::rtl() } else { Level::ltr() };
+  let bidi_info = BidiInfo::new(text, Some(level));
+  let mut runs = vec![];
+  for paragraph in &bidi_info.paragraphs {
+    let (levels, paragraph_runs) = bidi_info.visual_runs(paragraph, paragraph.range.clone());
+    for run in paragraph_runs {
+      let rtl = levels[run.start].is_rtl();
+      runs.push((run, rtl));
+    }
+  }
+  runs
+}
+
+#[cfg(test)]
+mod bidi_tests {
+  use super::*;
+
+  #[test]
+  fn should_keep_ltr_text() {
+    assert_eq!(visual_runs("abc", false), vec![(0..3, false)]);
+    assert_eq!(visual_runs("", true), vec![]);
+  }
+
+  #[test]
+  fn should_reorder_rtl_runs() {
+    // "abc " then three hebrew letters of two bytes each
+    let text = "abc \u{5d0}\u{5d1}\u{5d2}";
+    assert_eq!(visual_runs(text, false), vec![(0..4, false), (4..10, true)]);
+    // the space between both runs takes the paragraph direction
+    assert_eq!(visual_runs(text, true), vec![(3..10, true), (0..3, false)]);
+  }
+}
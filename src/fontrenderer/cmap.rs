@@ -0,0 +1,121 @@
+use super::sfnt::{FontFile, read_u16, read_u32};
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/cmap
+/// Glyph index of `c`, `None` when the font maps it to `.notdef`.
+pub fn glyph_index(font: &FontFile, c: char) -> Option<u32> {
+  cmap_glyph_index(font.table(b"cmap")?, c)
+}
+
+/// Same as `glyph_index` with the cmap table alone.
+pub fn cmap_glyph_index(cmap: &[u8], c: char) -> Option<u32> {
+  let num_tables = read_u16(cmap, 2)? as usize;
+  let mut best: Option<(u8, &[u8])> = None;
+  for i in 0..num_tables {
+    let record = 4 + 8 * i;
+    let platform = read_u16(cmap, record)?;
+    let encoding = read_u16(cmap, record + 2)?;
+    let subtable = cmap.get(read_u32(cmap, record + 4)? as usize..)?;
+    // full unicode tables first, then BMP ones
+    let priority = match (platform, encoding, read_u16(subtable, 0)?) {
+      (3, 10, 12) | (0, _, 12) => 0,
+      (3, 1, 4) | (0, _, 4) => 1,
+      _ => continue,
+    };
+    if best.map_or(true, |(p, _)| priority < p) {
+      best = Some((priority, subtable));
+    }
+  }
+
+  let (_, subtable) = best?;
+  let glyph = match read_u16(subtable, 0)? {
+    4 => format4_lookup(subtable, c as u32),
+    12 => format12_lookup(subtable, c as u32),
+    _ => None,
+  };
+  glyph.and_then(|g| if g == 0 { None } else { Some(g) })
+}
+
+fn format4_lookup(subtable: &[u8], c: u32) -> Option<u32> {
+  if c > 0xffff {
+    return None;
+  }
+  let seg_count = read_u16(subtable, 6)? as usize / 2;
+  let end_codes = 14;
+  let start_codes = end_codes + 2 * seg_count + 2;
+  let id_deltas = start_codes + 2 * seg_count;
+  let id_range_offsets = id_deltas + 2 * seg_count;
+  for i in 0..seg_count {
+    if (read_u16(subtable, end_codes + 2 * i)? as u32) < c {
+      continue;
+    }
+    let start = read_u16(subtable, start_codes + 2 * i)? as u32;
+    if start > c {
+      return None;
+    }
+    let delta = read_u16(subtable, id_deltas + 2 * i)? as u32;
+    let range_offset = read_u16(subtable, id_range_offsets + 2 * i)? as usize;
+    if range_offset == 0 {
+      return Some((c + delta) & 0xffff);
+    }
+    let address = id_range_offsets + 2 * i + range_offset + 2 * (c - start) as usize;
+    let glyph = read_u16(subtable, address)? as u32;
+    return if glyph == 0 { None } else { Some((glyph + delta) & 0xffff) };
+  }
+  None
+}
+
+fn format12_lookup(subtable: &[u8], c: u32) -> Option<u32> {
+  let num_groups = read_u32(subtable, 12)? as usize;
+  for i in 0..num_groups {
+    let group = 16 + 12 * i;
+    let start = read_u32(subtable, group)?;
+    let end = read_u32(subtable, group + 4)?;
+    if c >= start && c <= end {
+      return Some(read_u32(subtable, group + 8)? + c - start);
+    }
+  }
+  None
+}
+
+#[cfg(test)]
+mod cmap_tests {
+  use super::*;
+  use super::super::sfnt::test_font::*;
+
+  fn cmap(platform: u16, encoding: u16, subtable: Vec<u8>) -> Vec<u8> {
+    let mut cmap = vec![0, 0, 0, 1];
+    cmap.extend_from_slice(&u16_bytes(platform));
+    cmap.extend_from_slice(&u16_bytes(encoding));
+    cmap.extend_from_slice(&u32_bytes(12));
+    cmap.extend(subtable);
+    cmap
+  }
+
+  #[test]
+  fn should_lookup_format4() {
+    // 'A'..='C' mapped to 10..=12, then the terminating 0xffff segment
+    let mut subtable = vec![];
+    for value in &[4, 32, 0, 4, 4, 1, 0, 0x43, 0xffff, 0, 0x41, 0xffff, 10 - 0x41, 1, 0, 0] {
+      subtable.extend_from_slice(&u16_bytes(*value as u16));
+    }
+    let data = build_font(&[(b"cmap", cmap(3, 1, subtable))]);
+    let font = FontFile::new(&data, 0).unwrap();
+    assert_eq!(glyph_index(&font, 'A'), Some(10));
+    assert_eq!(glyph_index(&font, 'C'), Some(12));
+    assert_eq!(glyph_index(&font, 'D'), None);
+    assert_eq!(glyph_index(&font, '😀'), None);
+  }
+
+  #[test]
+  fn should_lookup_format12() {
+    let mut subtable = vec![0, 12, 0, 0, 0, 0, 0, 28, 0, 0, 0, 0, 0, 0, 0, 1];
+    for value in &[0x1f600u32, 0x1f64f, 100] {
+      subtable.extend_from_slice(&u32_bytes(*value));
+    }
+    let data = build_font(&[(b"cmap", cmap(3, 10, subtable))]);
+    let font = FontFile::new(&data, 0).unwrap();
+    assert_eq!(glyph_index(&font, '😀'), Some(100));
+    assert_eq!(glyph_index(&font, '😁'), Some(101));
+    assert_eq!(glyph_index(&font, 'A'), None);
+  }
+}
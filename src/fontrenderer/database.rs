@@ -0,0 +1,502 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, MutexGuard, Once};
+
+use fonts::system_fonts;
+
+use canvas::{CanvasError, FontFace, FontStretch, FontStyle};
+use super::cmap::{cmap_glyph_index, glyph_index};
+use super::font_context::{shared_font_context, FontKey};
+use super::metrics::{FontMetrics};
+use super::sfnt::{FontFile, read_u16};
+use super::shaper::{Shaper};
+
+lazy_static! {
+  static ref SHARED_FONT_DATABASE: Mutex<FontDatabase> = Mutex::new(FontDatabase::with_system_fonts());
+  static ref SYSTEM_FAMILIES: Vec<String> = system_fonts::query_all();
+}
+
+/// The font database shared by every canvas of the process.
+pub fn shared_font_database() -> MutexGuard<'static, FontDatabase> {
+  lock(&SHARED_FONT_DATABASE)
+}
+
+/// Reads a system family into the shared database the first time it is asked for.
+pub fn load_shared_family(family: &str) {
+  load_system_family(&SHARED_FONT_DATABASE, family)
+}
+
+/// Family of any face of the shared database or of the system covering `c`.
+pub fn shared_family_for_char(c: char) -> Option<String> {
+  family_for_char(&SHARED_FONT_DATABASE, c)
+}
+
+fn lock(database: &Mutex<FontDatabase>) -> MutexGuard<FontDatabase> {
+  // a canvas thread panicking while holding the lock leaves the faces intact
+  database.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Loads the regular, bold, italic and bold italic faces of a system family.
+/// Files are read without holding the lock of `database`, threads asking for
+/// the same family wait for the first one to be done.
+fn load_system_family(database: &Mutex<FontDatabase>, family: &str) {
+  let family = match SYSTEM_FAMILIES.iter().find(|f| f.eq_ignore_ascii_case(family)) {
+    Some(family) => family.clone(),
+    None => return,
+  };
+  let loading = match lock(database).family_loading(&family) {
+    Some(loading) => loading,
+    None => return,
+  };
+  loading.call_once(|| {
+    let variants: [fn(system_fonts::FontPropertyBuilder) -> system_fonts::FontPropertyBuilder; 4] = [
+      |builder| builder,
+      |builder| builder.bold(),
+      |builder| builder.italic(),
+      |builder| builder.bold().italic(),
+    ];
+    let faces: Vec<_> = variants.iter().filter_map(|variant| {
+      system_fonts::get(&variant(system_fonts::FontPropertyBuilder::new().family(&family)).build())
+    }).collect();
+    let mut database = lock(database);
+    for (buffer, index) in faces {
+      database.add_face(family.clone(), Arc::new(buffer), index as u32);
+    }
+  });
+}
+
+/// Family of any face covering `c`. The loaded faces are tried first, then the
+/// system families in turn, only the first system family covering `c` is loaded.
+fn family_for_char(database: &Mutex<FontDatabase>, c: char) -> Option<String> {
+  {
+    let mut database = lock(database);
+    if let Some(family) = database.char_families.get(&c) {
+      return family.clone();
+    }
+    let family = (0..database.faces.len())
+      .filter(|id| !database.replaced_faces.contains(id))
+      .map(|id| &database.faces[id])
+      .find(|face| face.has_glyph(c))
+      .map(|face| face.family.clone());
+    if family.is_some() || !database.system_fonts {
+      database.char_families.insert(c, family.clone());
+      return family;
+    }
+  }
+
+  let family = SYSTEM_FAMILIES.iter().find(|family| {
+    system_cmap(database, family).map_or(false, |cmap| cmap_glyph_index(&cmap, c).is_some())
+  }).cloned();
+  if let Some(ref family) = family {
+    load_system_family(database, family);
+  }
+  lock(database).char_families.insert(c, family.clone());
+  family
+}
+
+/// The cmap table of the regular face of a system family, kept once read so
+/// the following chars don't read the file again.
+fn system_cmap(database: &Mutex<FontDatabase>, family: &str) -> Option<Arc<Vec<u8>>> {
+  let key = family.to_lowercase();
+  if let Some(cmap) = lock(database).system_cmaps.get(&key) {
+    return cmap.clone();
+  }
+  // read without the lock, a thread racing for the same family reads it again
+  let property = system_fonts::FontPropertyBuilder::new().family(family).build();
+  let cmap = system_fonts::get(&property).and_then(|(buffer, index)| {
+    FontFile::new(&buffer, index as u32)?.table(b"cmap").map(|cmap| Arc::new(cmap.to_vec()))
+  });
+  lock(database).system_cmaps.insert(key, cmap.clone());
+  cmap
+}
+
+pub type FaceId = usize;
+
+const FS_SELECTION_ITALIC: u16 = 1;
+const FS_SELECTION_OBLIQUE: u16 = 1 << 9;
+const MAC_STYLE_ITALIC: u16 = 1 << 1;
+
+#[derive(Clone, Debug)]
+pub struct FaceInfo {
+  pub family: String,
+  pub weight: u16,
+  pub style: FontStyle,
+  pub stretch: FontStretch,
+  pub data: Arc<Vec<u8>>,
+  pub index: u32,
+  pub metrics: FontMetrics,
+}
+
+impl FaceInfo {
+  pub fn has_glyph(&self, c: char) -> bool {
+    FontFile::new(&self.data, self.index).and_then(|font| glyph_index(&font, c)).is_some()
+  }
+}
+
+/// A face parsed in the shared font context and by harfbuzz.
+#[derive(Clone)]
+pub struct LoadedFace {
+  pub font_key: FontKey,
+  pub shaper: Arc<Shaper>,
+}
+
+/// Faces indexed by family, weight, style and stretch.
+/// System families are only read from disk the first time they are queried.
+#[derive(Default)]
+pub struct FontDatabase {
+  faces: Vec<FaceInfo>,
+  system_fonts: bool,
+  /// System families read or being read from disk, by lowercase name.
+  system_families: BTreeMap<String, Arc<Once>>,
+  fallback_families: Vec<String>,
+  /// Family of the first face covering a char, see `family_for_char`.
+  char_families: BTreeMap<char, Option<String>>,
+  /// Cmap tables of the system families looked through by `family_for_char`.
+  system_cmaps: BTreeMap<String, Option<Arc<Vec<u8>>>>,
+  /// Faces parsed by `load_face`, shared by every canvas using the database.
+  loaded_faces: BTreeMap<FaceId, LoadedFace>,
+  /// Faces registered again with the same properties, left out of queries.
+  replaced_faces: BTreeSet<FaceId>,
+}
+
+impl FontDatabase {
+  pub fn new() -> FontDatabase {
+    FontDatabase::default()
+  }
+
+  pub fn with_system_fonts() -> FontDatabase {
+    FontDatabase { system_fonts: true, ..FontDatabase::default() }
+  }
+
+  /// Families tried after the families of the css `font` when a char is missing.
+  pub fn fallback_families(&self) -> &[String] {
+    &self.fallback_families
+  }
+
+  pub fn set_fallback_families(&mut self, families: Vec<String>) {
+    self.fallback_families = families;
+  }
+
+  /// The `Once` guarding the loading of a system family, `None` without system fonts.
+  fn family_loading(&mut self, family: &str) -> Option<Arc<Once>> {
+    if !self.system_fonts {
+      return None;
+    }
+    let loading = self.system_families.entry(family.to_lowercase()).or_insert_with(|| Arc::new(Once::new()));
+    Some(loading.clone())
+  }
+
+  /// Adds a face, its weight, style and stretch are read from the font file.
+  /// A face with the same properties as an existing face of the family is ignored.
+  pub fn add_face(&mut self, family: String, data: Arc<Vec<u8>>, index: u32) -> FaceId {
+    let (weight, style, stretch) = face_properties(&data, index);
+    let metrics = FontMetrics::from_bytes(&data, index).unwrap_or_default();
+    self.insert_face(FaceInfo { family, weight, style, stretch, data, index, metrics }, false)
+  }
+
+  /// Adds a user provided face, the descriptors of `face` override the properties
+  /// of the font file. A face registered with the same properties is replaced, the
+  /// new face gets its own id so canvases don't reuse what they parsed of the old one.
+  pub fn register_face(&mut self, face: &FontFace, data: Arc<Vec<u8>>) -> Result<FaceId, CanvasError> {
+    let metrics = FontMetrics::from_bytes(&data, face.index)
+      .ok_or_else(|| CanvasError::FontLoad(face.family.clone()))?;
+    let (weight, style, stretch) = face_properties(&data, face.index);
+    let info = FaceInfo {
+      family: face.family.clone(),
+      weight: face.weight.map(|w| w.max(1).min(1000)).unwrap_or(weight),
+      style: face.style.unwrap_or(style),
+      stretch: face.stretch.unwrap_or(stretch),
+      data,
+      index: face.index,
+      metrics,
+    };
+    Ok(self.insert_face(info, true))
+  }
+
+  fn insert_face(&mut self, info: FaceInfo, replace: bool) -> FaceId {
+    self.char_families.clear();
+    if let Some(id) = self.faces.iter().enumerate().position(|(id, face)| {
+      !self.replaced_faces.contains(&id) && face.family.eq_ignore_ascii_case(&info.family) &&
+        face.weight == info.weight && face.style == info.style && face.stretch == info.stretch
+    }) {
+      if !replace {
+        return id;
+      }
+      // the old face stays readable for the canvases already drawing with it
+      self.replaced_faces.insert(id);
+    }
+    self.faces.push(info);
+    self.faces.len() - 1
+  }
+
+  pub fn face(&self, id: FaceId) -> &FaceInfo {
+    &self.faces[id]
+  }
+
+  /// Parses a face the first time it is drawn, later calls return the same font key and shaper.
+  pub fn load_face(&mut self, id: FaceId) -> Result<LoadedFace, CanvasError> {
+    if let Some(loaded) = self.loaded_faces.get(&id) {
+      return Ok(loaded.clone());
+    }
+    let face = self.faces[id].clone();
+    let font_key = FontKey::new();
+    shared_font_context().add_font_from_memory(&font_key, face.data.clone(), face.index)
+      .map_err(|_| CanvasError::FontLoad(face.family.clone()))?;
+    let loaded = LoadedFace { font_key, shaper: Arc::new(Shaper::new(face.data, face.index)) };
+    self.loaded_faces.insert(id, loaded.clone());
+    Ok(loaded)
+  }
+
+  // https://drafts.csswg.org/css-fonts/#font-style-matching
+  /// Only looks through loaded faces, see `load_shared_family` for system fonts.
+  pub fn query(&self, family: &str, weight: u16, style: FontStyle, stretch: FontStretch) -> Option<FaceId> {
+    let mut candidates: Vec<FaceId> = (0..self.faces.len())
+      .filter(|id| !self.replaced_faces.contains(id))
+      .filter(|&id| self.faces[id].family.eq_ignore_ascii_case(family))
+      .collect();
+
+    let best_stretch = candidates.iter().map(|&id| self.faces[id].stretch)
+      .min_by_key(|&s| stretch_distance(stretch, s))?;
+    candidates.retain(|&id| self.faces[id].stretch == best_stretch);
+
+    let best_style = candidates.iter().map(|&id| self.faces[id].style)
+      .min_by_key(|s| style_distance(&style, s))?;
+    candidates.retain(|&id| self.faces[id].style == best_style);
+
+    candidates.into_iter().min_by_key(|&id| weight_distance(weight, self.faces[id].weight))
+  }
+}
+
+impl Drop for FontDatabase {
+  fn drop(&mut self) {
+    let mut font_context = shared_font_context();
+    for loaded in self.loaded_faces.values() {
+      font_context.delete_font(&loaded.font_key);
+    }
+  }
+}
+
+/// Narrower faces are preferred for condensed requests, wider ones otherwise.
+fn stretch_distance(desired: FontStretch, available: FontStretch) -> (u8, u8) {
+  let (desired, available) = (desired as i8, available as i8);
+  let normal = FontStretch::Normal as i8;
+  let diff = available - desired;
+  if diff == 0 {
+    (0, 0)
+  } else if (desired <= normal) == (diff < 0) {
+    (1, diff.abs() as u8)
+  } else {
+    (2, diff.abs() as u8)
+  }
+}
+
+fn style_distance(desired: &FontStyle, available: &FontStyle) -> u8 {
+  let order = match *desired {
+    FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+    FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+    FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+  };
+  order.iter().position(|s| s == available).unwrap_or(order.len()) as u8
+}
+
+fn weight_distance(desired: u16, available: u16) -> (u8, u16) {
+  let (d, a) = (desired as i32, available as i32);
+  let distance = (a - d).abs() as u16;
+  if a == d {
+    (0, 0)
+  } else if desired >= 400 && desired <= 500 {
+    // up to 500 first, then lighter, then heavier
+    if a > d && a <= 500 {
+      (1, distance)
+    } else if a < d {
+      (2, distance)
+    } else {
+      (3, distance)
+    }
+  } else if (desired < 400) == (a < d) {
+    (1, distance)
+  } else {
+    (2, distance)
+  }
+}
+
+/// Weight, style and stretch from the OS/2 table, falling back to `head.macStyle`.
+fn face_properties(data: &[u8], index: u32) -> (u16, FontStyle, FontStretch) {
+  let mut weight = 400;
+  let mut style = FontStyle::Normal;
+  let mut stretch = FontStretch::Normal;
+  let font = match FontFile::new(data, index) {
+    Some(font) => font,
+    None => return (weight, style, stretch),
+  };
+
+  if let Some(os2) = font.table(b"OS/2") {
+    if let Some(w) = read_u16(os2, 4) {
+      weight = w.max(1).min(1000);
+    }
+    if let Some(width) = read_u16(os2, 6) {
+      stretch = match width {
+        1 => FontStretch::UltraCondensed,
+        2 => FontStretch::ExtraCondensed,
+        3 => FontStretch::Condensed,
+        4 => FontStretch::SemiCondensed,
+        6 => FontStretch::SemiExpanded,
+        7 => FontStretch::Expanded,
+        8 => FontStretch::ExtraExpanded,
+        9 => FontStretch::UltraExpanded,
+        _ => FontStretch::Normal,
+      };
+    }
+    if let Some(selection) = read_u16(os2, 62) {
+      if selection & FS_SELECTION_OBLIQUE != 0 {
+        style = FontStyle::Oblique;
+      } else if selection & FS_SELECTION_ITALIC != 0 {
+        style = FontStyle::Italic;
+      }
+    }
+  } else if let Some(mac_style) = font.table(b"head").and_then(|head| read_u16(head, 44)) {
+    if mac_style & MAC_STYLE_ITALIC != 0 {
+      style = FontStyle::Italic;
+    }
+    if mac_style & 1 != 0 {
+      weight = 700;
+    }
+  }
+  (weight, style, stretch)
+}
+
+#[cfg(test)]
+mod font_database_tests {
+  use super::*;
+  use super::super::sfnt::test_font::*;
+  use csshelper::{SANS_SERIF_FONT_FAMILY};
+
+  fn face(weight: u16, width: u16, selection: u16) -> Arc<Vec<u8>> {
+    let mut os2 = vec![];
+    put_u16(&mut os2, 4, weight);
+    put_u16(&mut os2, 6, width);
+    put_u16(&mut os2, 62, selection);
+    os2.resize(78, 0);
+    Arc::new(build_font(&[(b"OS/2", os2)]))
+  }
+
+  fn database() -> FontDatabase {
+    let mut db = FontDatabase::new();
+    db.add_face("Roboto".to_string(), face(400, 5, 0), 0);
+    db.add_face("Roboto".to_string(), face(700, 5, 0), 0);
+    db.add_face("Roboto".to_string(), face(300, 5, FS_SELECTION_ITALIC), 0);
+    db.add_face("Roboto".to_string(), face(400, 3, 0), 0);
+    db
+  }
+
+  #[test]
+  fn should_read_face_properties() {
+    let db = database();
+    let info = db.face(2);
+    assert_eq!(info.weight, 300);
+    assert_eq!(info.style, FontStyle::Italic);
+    assert_eq!(db.face(3).stretch, FontStretch::Condensed);
+  }
+
+  #[test]
+  fn should_register_face_with_descriptors() {
+    let mut db = database();
+    let mut head = vec![];
+    put_u16(&mut head, 18, 1000);
+    let data = Arc::new(build_font(&[(b"head", head), (b"hhea", vec![0; 36])]));
+    let mut face = FontFace::new("Brand");
+    face.weight = Some(700);
+    face.style = Some(FontStyle::Italic);
+    let id = db.register_face(&face, data.clone()).unwrap();
+    assert_eq!(db.query("brand", 700, FontStyle::Italic, FontStretch::Normal), Some(id));
+    // a replaced face gets a new id, canvases can't mistake it for the old one
+    let new_id = db.register_face(&face, data).unwrap();
+    assert_ne!(new_id, id);
+    assert_eq!(db.query("brand", 700, FontStyle::Italic, FontStretch::Normal), Some(new_id));
+  }
+
+  #[test]
+  fn should_register_face_of_collection() {
+    let mut head = vec![];
+    put_u16(&mut head, 18, 1000);
+    let font = |weight: u16| {
+      let mut os2 = vec![];
+      put_u16(&mut os2, 4, weight);
+      os2.resize(78, 0);
+      vec![(b"OS/2", os2), (b"head", head.clone()), (b"hhea", vec![0; 36])]
+    };
+    let data = Arc::new(build_collection(&[&font(400), &font(700)]));
+    let mut db = FontDatabase::new();
+    let mut face = FontFace::new("Brand");
+    face.index = 1;
+    let id = db.register_face(&face, data.clone()).unwrap();
+    assert_eq!((db.face(id).weight, db.face(id).index), (700, 1));
+    face.index = 2;
+    assert_eq!(db.register_face(&face, data), Err(CanvasError::FontLoad("Brand".to_string())));
+  }
+
+  #[test]
+  fn should_reject_invalid_font_data() {
+    let mut db = FontDatabase::new();
+    let result = db.register_face(&FontFace::new("Brand"), Arc::new(vec![1, 2, 3]));
+    assert_eq!(result, Err(CanvasError::FontLoad("Brand".to_string())));
+  }
+
+  #[test]
+  fn should_find_family_covering_char() {
+    let mut db = database();
+    let mut subtable = vec![0, 12, 0, 0, 0, 0, 0, 28, 0, 0, 0, 0, 0, 0, 0, 1];
+    for value in &[0x4e00u32, 0x9fff, 1] {
+      subtable.extend_from_slice(&u32_bytes(*value));
+    }
+    let mut cmap = vec![0, 0, 0, 1, 0, 3, 0, 10, 0, 0, 0, 12];
+    cmap.extend(subtable);
+    db.add_face("Noto Sans CJK".to_string(), Arc::new(build_font(&[(b"cmap", cmap)])), 0);
+    let database = Mutex::new(db);
+    assert_eq!(family_for_char(&database, '人'), Some("Noto Sans CJK".to_string()));
+    assert_eq!(family_for_char(&database, '😀'), None);
+  }
+
+  #[test]
+  fn should_ignore_duplicated_face() {
+    let mut db = database();
+    assert_eq!(db.add_face("roboto".to_string(), face(700, 5, 0), 0), 1);
+  }
+
+  #[test]
+  fn should_match_weight_style_and_stretch() {
+    let db = database();
+    assert_eq!(db.query("roboto", 700, FontStyle::Normal, FontStretch::Normal), Some(1));
+    assert_eq!(db.query("Roboto", 600, FontStyle::Normal, FontStretch::Normal), Some(1));
+    assert_eq!(db.query("Roboto", 450, FontStyle::Normal, FontStretch::Normal), Some(0));
+    assert_eq!(db.query("Roboto", 700, FontStyle::Oblique, FontStretch::Normal), Some(2));
+    assert_eq!(db.query("Roboto", 400, FontStyle::Normal, FontStretch::SemiCondensed), Some(3));
+    assert_eq!(db.query("Roboto", 400, FontStyle::Normal, FontStretch::Expanded), Some(0));
+    assert_eq!(db.query("Arial", 400, FontStyle::Normal, FontStretch::Normal), None);
+  }
+
+  #[test]
+  fn should_load_system_family_when_asked_for() {
+    let database = Mutex::new(FontDatabase::with_system_fonts());
+    assert!(lock(&database).query(SANS_SERIF_FONT_FAMILY, 400, FontStyle::Normal, FontStretch::Normal).is_none());
+    load_system_family(&database, SANS_SERIF_FONT_FAMILY);
+    let db = lock(&database);
+    assert!(db.query(SANS_SERIF_FONT_FAMILY, 400, FontStyle::Normal, FontStretch::Normal).is_some());
+    // the other system families are left on disk
+    assert!(db.faces.iter().all(|face| face.family.eq_ignore_ascii_case(SANS_SERIF_FONT_FAMILY)));
+  }
+
+  #[test]
+  fn should_not_load_system_fonts_without_system_fonts() {
+    let database = Mutex::new(FontDatabase::new());
+    load_system_family(&database, SANS_SERIF_FONT_FAMILY);
+    assert!(lock(&database).faces.is_empty());
+  }
+
+  #[test]
+  fn should_prefer_lighter_weight_for_normal_request() {
+    assert!(weight_distance(400, 500) < weight_distance(400, 300));
+    assert!(weight_distance(400, 300) < weight_distance(400, 600));
+    assert!(weight_distance(300, 200) < weight_distance(300, 400));
+    assert!(weight_distance(600, 800) < weight_distance(600, 500));
+  }
+}
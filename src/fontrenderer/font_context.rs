@@ -0,0 +1,47 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{ATOMIC_USIZE_INIT, AtomicUsize, Ordering};
+
+use pathfinder_font_renderer::{FontContext};
+
+static NEXT_FONT_KEY: AtomicUsize = ATOMIC_USIZE_INIT;
+
+lazy_static! {
+  static ref SHARED_FONT_CONTEXT: Mutex<SharedFontContext> =
+    Mutex::new(SharedFontContext(FontContext::new().expect("init FontContext fail")));
+}
+
+/// Key of a face in the shared font context.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Eq, Ord, Hash)]
+pub struct FontKey(usize);
+
+impl FontKey {
+  pub fn new() -> FontKey {
+    FontKey(NEXT_FONT_KEY.fetch_add(1, Ordering::SeqCst))
+  }
+}
+
+/// The FreeType faces of every canvas of the process, a face is parsed once
+/// however many canvases draw with it.
+pub struct SharedFontContext(FontContext<FontKey>);
+
+// FreeType objects are only touched while holding the lock of `shared_font_context`
+unsafe impl Send for SharedFontContext {}
+
+impl Deref for SharedFontContext {
+  type Target = FontContext<FontKey>;
+
+  fn deref(&self) -> &FontContext<FontKey> {
+    &self.0
+  }
+}
+
+impl DerefMut for SharedFontContext {
+  fn deref_mut(&mut self) -> &mut FontContext<FontKey> {
+    &mut self.0
+  }
+}
+
+pub fn shared_font_context() -> MutexGuard<'static, SharedFontContext> {
+  SHARED_FONT_CONTEXT.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash};
+use std::rc::{Rc};
+
+use euclid::{Point2D, Rect, Size2D};
+use lyon_path::{PathEvent};
+
+pub const DEFAULT_GLYPH_CACHE_LIMIT: usize = 4096;
+
+/// Outline of a glyph in pixels, y up from the glyph origin.
+#[derive(Debug)]
+pub struct CachedGlyph {
+  pub outline: Vec<PathEvent>,
+  /// Bounds of the outline points, `None` for blank glyphs.
+  pub bounds: Option<Rect<f32>>,
+}
+
+impl CachedGlyph {
+  pub fn new(outline: Vec<PathEvent>) -> CachedGlyph {
+    let mut points = outline.iter().flat_map(|event| match *event {
+      PathEvent::MoveTo(p) | PathEvent::LineTo(p) => vec![p],
+      PathEvent::QuadraticTo(cp, p) => vec![cp, p],
+      PathEvent::CubicTo(cp1, cp2, p) => vec![cp1, cp2, p],
+      _ => vec![],
+    });
+    let bounds = points.next().map(|first| {
+      let (min, max) = points.fold((first, first), |(min, max), p| {
+        (Point2D::new(min.x.min(p.x), min.y.min(p.y)), Point2D::new(max.x.max(p.x), max.y.max(p.y)))
+      });
+      Rect::new(min, Size2D::new(max.x - min.x, max.y - min.y))
+    });
+    CachedGlyph { outline, bounds }
+  }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct GlyphCacheStats {
+  pub hits: u64,
+  pub misses: u64,
+  pub evictions: u64,
+  pub len: usize,
+  pub limit: usize,
+}
+
+/// Least recently used cache of glyph outlines.
+pub struct GlyphCache<K> {
+  entries: HashMap<K, (Rc<CachedGlyph>, u64)>,
+  /// Keys by the tick of their last use, the first one is evicted next.
+  recently_used: BTreeMap<u64, K>,
+  limit: usize,
+  tick: u64,
+  stats: GlyphCacheStats,
+}
+
+impl <K: Clone + Eq + Hash> GlyphCache<K> {
+  pub fn new(limit: usize) -> GlyphCache<K> {
+    GlyphCache {
+      entries: HashMap::new(),
+      recently_used: BTreeMap::new(),
+      limit,
+      tick: 0,
+      stats: GlyphCacheStats::default(),
+    }
+  }
+
+  pub fn get_or_insert_with<F, E>(&mut self, key: K, load: F) -> Result<Rc<CachedGlyph>, E>
+      where F: FnOnce() -> Result<CachedGlyph, E> {
+    self.tick += 1;
+    if let Some(entry) = self.entries.get_mut(&key) {
+      let key = self.recently_used.remove(&entry.1).unwrap_or(key);
+      self.recently_used.insert(self.tick, key);
+      entry.1 = self.tick;
+      self.stats.hits += 1;
+      return Ok(entry.0.clone());
+    }
+
+    self.stats.misses += 1;
+    let glyph = Rc::new(load()?);
+    if self.limit == 0 {
+      return Ok(glyph);
+    }
+    while self.entries.len() >= self.limit {
+      self.evict_least_recently_used();
+    }
+    self.recently_used.insert(self.tick, key.clone());
+    self.entries.insert(key, (glyph.clone(), self.tick));
+    Ok(glyph)
+  }
+
+  /// Shrinks the cache right away if it holds more than `limit` glyphs, 0 disables caching.
+  pub fn set_limit(&mut self, limit: usize) {
+    self.limit = limit;
+    while self.entries.len() > limit {
+      self.evict_least_recently_used();
+    }
+  }
+
+  pub fn stats(&self) -> GlyphCacheStats {
+    GlyphCacheStats { len: self.entries.len(), limit: self.limit, ..self.stats }
+  }
+
+  fn evict_least_recently_used(&mut self) {
+    let oldest = self.recently_used.keys().next().cloned();
+    if let Some(key) = oldest.and_then(|tick| self.recently_used.remove(&tick)) {
+      self.entries.remove(&key);
+      self.stats.evictions += 1;
+    }
+  }
+}
+
+#[cfg(test)]
+mod glyph_cache_tests {
+  use super::*;
+
+  fn glyph() -> Result<CachedGlyph, ()> {
+    Ok(CachedGlyph::new(vec![
+      PathEvent::MoveTo(Point2D::new(1.0, -2.0)),
+      PathEvent::LineTo(Point2D::new(5.0, 8.0)),
+      PathEvent::Close,
+    ]))
+  }
+
+  #[test]
+  fn should_compute_bounds() {
+    let bounds = glyph().unwrap().bounds.unwrap();
+    assert_eq!(bounds, Rect::new(Point2D::new(1.0, -2.0), Size2D::new(4.0, 10.0)));
+    assert_eq!(CachedGlyph::new(vec![]).bounds, None);
+  }
+
+  #[test]
+  fn should_count_hits_and_misses() {
+    let mut cache = GlyphCache::new(2);
+    cache.get_or_insert_with(1, glyph).unwrap();
+    cache.get_or_insert_with(1, || -> Result<CachedGlyph, ()> { panic!("should hit the cache") }).unwrap();
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.len), (1, 1, 1));
+  }
+
+  #[test]
+  fn should_evict_least_recently_used() {
+    let mut cache = GlyphCache::new(2);
+    cache.get_or_insert_with(1, glyph).unwrap();
+    cache.get_or_insert_with(2, glyph).unwrap();
+    cache.get_or_insert_with(1, glyph).unwrap();
+    cache.get_or_insert_with(3, glyph).unwrap();
+    cache.get_or_insert_with(1, glyph).unwrap();
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.evictions, stats.len), (2, 3, 1, 2));
+    cache.set_limit(1);
+    assert_eq!(cache.stats().len, 1);
+  }
+
+  #[test]
+  fn should_not_cache_load_errors() {
+    let mut cache: GlyphCache<u32> = GlyphCache::new(2);
+    assert_eq!(cache.get_or_insert_with(1, || Err("broken")).err(), Some("broken"));
+    assert_eq!(cache.stats().len, 0);
+  }
+}
@@ -0,0 +1,118 @@
+use super::sfnt::{FontFile, read_i16, read_u16};
+
+const USE_TYPO_METRICS: u16 = 1 << 7;
+
+/// Vertical metrics of a face, ascent and descent are both positive distances
+/// from the alphabetic baseline.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontMetrics {
+  pub units_per_em: f32,
+  pub ascent: f32,
+  pub descent: f32,
+  pub line_gap: f32,
+}
+
+impl Default for FontMetrics {
+  fn default() -> FontMetrics {
+    FontMetrics {
+      units_per_em: 1000.0,
+      ascent: 800.0,
+      descent: 200.0,
+      line_gap: 0.0,
+    }
+  }
+}
+
+impl FontMetrics {
+  pub fn from_bytes(bytes: &[u8], index: u32) -> Option<FontMetrics> {
+    let font = FontFile::new(bytes, index)?;
+    let head = font.table(b"head")?;
+    let hhea = font.table(b"hhea")?;
+    let units_per_em = read_u16(head, 18)? as f32;
+    let mut metrics = FontMetrics {
+      units_per_em,
+      ascent: read_i16(hhea, 4)? as f32,
+      descent: -read_i16(hhea, 6)? as f32,
+      line_gap: read_i16(hhea, 8)? as f32,
+    };
+
+    if let Some(os2) = font.table(b"OS/2") {
+      let use_typo_metrics = read_u16(os2, 62).map(|s| s & USE_TYPO_METRICS != 0).unwrap_or(false);
+      if use_typo_metrics || metrics.ascent == 0.0 && metrics.descent == 0.0 {
+        if let (Some(ascent), Some(descent), Some(line_gap)) =
+            (read_i16(os2, 68), read_i16(os2, 70), read_i16(os2, 72)) {
+          metrics.ascent = ascent as f32;
+          metrics.descent = -descent as f32;
+          metrics.line_gap = line_gap as f32;
+        }
+      }
+    }
+
+    if units_per_em == 0.0 {
+      return None;
+    }
+    Some(metrics)
+  }
+
+  /// Metrics in pixels for the given font size.
+  pub fn scale(&self, font_size: f32) -> FontMetrics {
+    let scale = font_size / self.units_per_em;
+    FontMetrics {
+      units_per_em: font_size,
+      ascent: self.ascent * scale,
+      descent: self.descent * scale,
+      line_gap: self.line_gap * scale,
+    }
+  }
+}
+
+#[cfg(test)]
+mod font_metrics_tests {
+  use super::*;
+  use super::super::sfnt::test_font::*;
+
+  fn head(units_per_em: u16) -> Vec<u8> {
+    let mut head = vec![];
+    put_u16(&mut head, 18, units_per_em);
+    head.resize(54, 0);
+    head
+  }
+
+  fn hhea(ascent: i16, descent: i16, line_gap: i16) -> Vec<u8> {
+    let mut hhea = vec![];
+    put_u16(&mut hhea, 4, ascent as u16);
+    put_u16(&mut hhea, 6, descent as u16);
+    put_u16(&mut hhea, 8, line_gap as u16);
+    hhea.resize(36, 0);
+    hhea
+  }
+
+  #[test]
+  fn should_read_hhea_metrics() {
+    let font = build_font(&[(b"head", head(2048)), (b"hhea", hhea(1900, -500, 0))]);
+    let metrics = FontMetrics::from_bytes(&font, 0).unwrap();
+    assert_eq!(metrics.ascent, 1900.0);
+    assert_eq!(metrics.descent, 500.0);
+    let scaled = metrics.scale(20.48);
+    assert!((scaled.ascent - 19.0).abs() < 1e-4);
+    assert!((scaled.descent - 5.0).abs() < 1e-4);
+  }
+
+  #[test]
+  fn should_prefer_typo_metrics() {
+    let mut os2 = vec![];
+    put_u16(&mut os2, 62, USE_TYPO_METRICS);
+    put_u16(&mut os2, 68, 800);
+    put_u16(&mut os2, 70, -200i16 as u16);
+    put_u16(&mut os2, 72, 90);
+    os2.resize(78, 0);
+    let font = build_font(&[(b"OS/2", os2), (b"head", head(1000)), (b"hhea", hhea(900, -300, 0))]);
+    let metrics = FontMetrics::from_bytes(&font, 0).unwrap();
+    assert_eq!(metrics, FontMetrics { units_per_em: 1000.0, ascent: 800.0, descent: 200.0, line_gap: 90.0 });
+  }
+
+  #[test]
+  fn should_reject_broken_font() {
+    assert_eq!(FontMetrics::from_bytes(&[0, 1, 0, 0], 0), None);
+  }
+}
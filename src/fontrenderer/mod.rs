@@ -1,6 +1,28 @@
+mod bidi;
+mod cmap;
+mod database;
+mod font_context;
+mod glyph_cache;
+mod metrics;
+mod sfnt;
+mod shaper;
+mod synthesis;
+mod vertical;
+
 use euclid::{Transform2D};
 use lyon_path::{PathEvent};
 
+pub use self::bidi::*;
+pub use self::database::*;
+pub use self::font_context::*;
+pub use self::glyph_cache::*;
+pub use self::metrics::*;
+pub use self::shaper::*;
+pub use self::synthesis::*;
+pub use self::vertical::*;
+#[cfg(test)]
+pub use self::sfnt::test_font;
+
 pub fn flip_text(scale: f32) -> Box<Fn(PathEvent) -> PathEvent> {
   let flip = move |event: PathEvent| -> PathEvent {
     let text_transform: Transform2D<f32> = Transform2D::from_row_major_array([
@@ -0,0 +1,181 @@
+// https://docs.microsoft.com/en-us/typography/opentype/spec/otff
+const TTC_TAG: u32 = 0x74746366; // 'ttcf'
+
+/// A minimal reader of the OpenType table directory, only used to
+/// read the tables pathfinder doesn't expose.
+#[derive(Clone, Copy)]
+pub struct FontFile<'a> {
+  data: &'a [u8],
+  offset: usize,
+}
+
+impl <'a> FontFile<'a> {
+  pub fn new(data: &'a [u8], index: u32) -> Option<FontFile<'a>> {
+    let offset = if read_u32(data, 0)? == TTC_TAG {
+      if index >= read_u32(data, 8)? {
+        return None;
+      }
+      read_u32(data, 12 + 4 * index as usize)? as usize
+    } else {
+      0
+    };
+    Some(FontFile { data, offset })
+  }
+
+  pub fn table(&self, tag: &[u8; 4]) -> Option<&'a [u8]> {
+    let num_tables = read_u16(self.data, self.offset + 4)? as usize;
+    for i in 0..num_tables {
+      let record = self.offset + 12 + 16 * i;
+      if self.data.get(record..record + 4)? == tag {
+        let offset = read_u32(self.data, record + 8)? as usize;
+        let length = read_u32(self.data, record + 12)? as usize;
+        return self.data.get(offset..offset + length);
+      }
+    }
+    None
+  }
+}
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+  data.get(offset..offset + 2).map(|b| (b[0] as u16) << 8 | b[1] as u16)
+}
+
+pub fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+  read_u16(data, offset).map(|v| v as i16)
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+  data.get(offset..offset + 4)
+    .map(|b| (b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+}
+
+#[cfg(test)]
+pub mod test_font {
+  /// Assembles a font file out of raw tables, tables must be sorted by tag.
+  pub fn build_font(tables: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+    let mut data = vec![0, 1, 0, 0];
+    data.extend_from_slice(&[0, tables.len() as u8, 0, 0, 0, 0, 0, 0]);
+    let mut offset = 12 + 16 * tables.len();
+    for &(tag, ref table) in tables {
+      data.extend_from_slice(tag);
+      data.extend_from_slice(&[0, 0, 0, 0]);
+      data.extend_from_slice(&u32_bytes(offset as u32));
+      data.extend_from_slice(&u32_bytes(table.len() as u32));
+      offset += table.len();
+    }
+    for &(_, ref table) in tables {
+      data.extend_from_slice(table);
+    }
+    data
+  }
+
+  /// A TrueType font with empty outlines mapping `chars` to glyphs 1 and up,
+  /// 1000 units per em and 500 units wide glyphs.
+  pub fn build_test_face(chars: &[char]) -> Vec<u8> {
+    let num_glyphs = chars.len() as u16 + 1;
+    let mut cmap = vec![0, 0, 0, 1, 0, 3, 0, 10, 0, 0, 0, 12, 0, 12, 0, 0];
+    cmap.extend_from_slice(&u32_bytes(16 + 12 * chars.len() as u32));
+    cmap.extend_from_slice(&u32_bytes(0));
+    cmap.extend_from_slice(&u32_bytes(chars.len() as u32));
+    for (i, c) in chars.iter().enumerate() {
+      cmap.extend_from_slice(&u32_bytes(*c as u32));
+      cmap.extend_from_slice(&u32_bytes(*c as u32));
+      cmap.extend_from_slice(&u32_bytes(i as u32 + 1));
+    }
+    let mut head = vec![0, 1, 0, 0];
+    put_u16(&mut head, 12, 0x5f0f);
+    put_u16(&mut head, 14, 0x3cf5);
+    put_u16(&mut head, 18, 1000);
+    head.resize(54, 0);
+    let mut hhea = vec![0, 1, 0, 0];
+    put_u16(&mut hhea, 4, 800);
+    put_u16(&mut hhea, 6, -200i16 as u16);
+    put_u16(&mut hhea, 10, 500);
+    put_u16(&mut hhea, 34, num_glyphs);
+    let mut hmtx = vec![];
+    for _ in 0..num_glyphs {
+      hmtx.extend_from_slice(&[1, 0xf4, 0, 0]);
+    }
+    let mut maxp = vec![0, 1, 0, 0];
+    put_u16(&mut maxp, 4, num_glyphs);
+    maxp.resize(32, 0);
+    build_font(&[
+      (b"cmap", cmap),
+      (b"glyf", vec![0; 4]),
+      (b"head", head),
+      (b"hhea", hhea),
+      (b"hmtx", hmtx),
+      (b"loca", vec![0; 2 * (num_glyphs as usize + 1)]),
+      (b"maxp", maxp),
+    ])
+  }
+
+  /// Assembles a font collection, tables of each font must be sorted by tag.
+  pub fn build_collection(fonts: &[&[(&[u8; 4], Vec<u8>)]]) -> Vec<u8> {
+    let mut data = b"ttcf".to_vec();
+    data.extend_from_slice(&[0, 1, 0, 0]);
+    data.extend_from_slice(&u32_bytes(fonts.len() as u32));
+    // the table directories of every font, then every table
+    let mut offset = 12 + 4 * fonts.len();
+    for tables in fonts {
+      data.extend_from_slice(&u32_bytes(offset as u32));
+      offset += 12 + 16 * tables.len();
+    }
+    for tables in fonts {
+      data.extend_from_slice(&[0, 1, 0, 0, 0, tables.len() as u8, 0, 0, 0, 0, 0, 0]);
+      for &(tag, ref table) in tables.iter() {
+        data.extend_from_slice(tag);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(&u32_bytes(offset as u32));
+        data.extend_from_slice(&u32_bytes(table.len() as u32));
+        offset += table.len();
+      }
+    }
+    for tables in fonts {
+      for &(_, ref table) in tables.iter() {
+        data.extend_from_slice(table);
+      }
+    }
+    data
+  }
+
+  pub fn u16_bytes(value: u16) -> [u8; 2] {
+    [(value >> 8) as u8, value as u8]
+  }
+
+  pub fn u32_bytes(value: u32) -> [u8; 4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+  }
+
+  /// Writes `value` at `offset`, growing the table if needed.
+  pub fn put_u16(table: &mut Vec<u8>, offset: usize, value: u16) {
+    if table.len() < offset + 2 {
+      table.resize(offset + 2, 0);
+    }
+    table[offset..offset + 2].copy_from_slice(&u16_bytes(value));
+  }
+}
+
+#[cfg(test)]
+mod sfnt_tests {
+  use super::*;
+  use super::test_font::*;
+
+  #[test]
+  fn should_find_table() {
+    let data = build_font(&[(b"head", vec![1, 2, 3]), (b"hhea", vec![4, 5])]);
+    let font = FontFile::new(&data, 0).unwrap();
+    assert_eq!(font.table(b"head"), Some(&[1u8, 2, 3][..]));
+    assert_eq!(font.table(b"hhea"), Some(&[4u8, 5][..]));
+    assert_eq!(font.table(b"OS/2"), None);
+  }
+
+  #[test]
+  fn should_read_numbers() {
+    let data = [0xff, 0xfe, 0x00, 0x01];
+    assert_eq!(read_u16(&data, 0), Some(0xfffe));
+    assert_eq!(read_i16(&data, 0), Some(-2));
+    assert_eq!(read_u32(&data, 0), Some(0xfffe0001));
+    assert_eq!(read_u16(&data, 3), None);
+  }
+}
@@ -0,0 +1,192 @@
+use std::ops::{Range};
+use std::sync::{Arc};
+
+use harfbuzz_rs::{hb, Blob, Direction, Face, Feature, Font, Script, Shared, Tag, UnicodeBuffer, shape};
+
+use super::sfnt::{FontFile, read_u16};
+
+/// A glyph produced by the shaper, positions are in font units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShapedGlyph {
+  pub glyph_id: u32,
+  /// Byte offset of the first char of the cluster in the shaped text.
+  pub cluster: u32,
+  pub x_advance: f32,
+  pub y_advance: f32,
+  pub x_offset: f32,
+  pub y_offset: f32,
+}
+
+/// OpenType shaping of a face with harfbuzz, applies GSUB and GPOS with the
+/// default features (kerning, ligatures, contextual forms and marks).
+/// A shaper can be used from every canvas thread at once.
+pub struct Shaper {
+  font: Shared<Font<'static>>,
+  small_caps: bool,
+}
+
+impl Shaper {
+  pub fn new(data: Arc<Vec<u8>>, index: u32) -> Shaper {
+    let small_caps = has_substitution(&data, index, b"smcp");
+    let blob = Blob::with_bytes_owned(data, |data| &data[..]);
+    Shaper { font: Font::new(Face::new(blob, index)).into(), small_caps }
+  }
+
+  /// Whether the face has real small caps, lowercase letters are substituted when shaping with `small_caps`.
+  pub fn has_small_caps(&self) -> bool {
+    self.small_caps
+  }
+
+  /// Shapes a directional run of a single face, split in runs of a single script.
+  /// Glyphs of right to left runs are returned in visual order.
+  pub fn shape(&self, text: &str, rtl: bool, small_caps: bool) -> Vec<ShapedGlyph> {
+    let direction = if rtl { Direction::Rtl } else { Direction::Ltr };
+    self.shape_with(text, direction, features(small_caps, false))
+  }
+
+  /// Shapes a top to bottom run with the vertical alternates of the face,
+  /// advances and glyph origins come from its vhea and vmtx tables.
+  /// `y_advance` is negative as positions are y up.
+  pub fn shape_vertical(&self, text: &str, small_caps: bool) -> Vec<ShapedGlyph> {
+    self.shape_with(text, Direction::Ttb, features(small_caps, true))
+  }
+
+  /// Clusters are byte offsets in the whole `text`, the other runs are the context
+  /// of each script run.
+  fn shape_with(&self, text: &str, direction: Direction, features: Vec<Feature>) -> Vec<ShapedGlyph> {
+    let mut runs = script_runs(text);
+    if direction == Direction::Rtl {
+      runs.reverse();
+    }
+    let mut glyphs = vec![];
+    for (range, script) in runs {
+      let buffer = UnicodeBuffer::new().add_str_item(text, &text[range])
+        .set_direction(direction)
+        .set_script(script.to_iso15924_tag())
+        .guess_segment_properties();
+      let output = shape(&self.font, buffer, &features);
+      glyphs.extend(output.get_glyph_infos().iter().zip(output.get_glyph_positions())
+        .map(|(info, position)| ShapedGlyph {
+          glyph_id: info.codepoint,
+          cluster: info.cluster,
+          x_advance: position.x_advance as f32,
+          y_advance: position.y_advance as f32,
+          x_offset: position.x_offset as f32,
+          y_offset: position.y_offset as f32,
+        }));
+    }
+    glyphs
+  }
+}
+
+fn features(small_caps: bool, vertical: bool) -> Vec<Feature> {
+  let mut features = vec![];
+  if small_caps {
+    features.push(Feature::new(Tag::new('s', 'm', 'c', 'p'), 1, ..));
+  }
+  if vertical {
+    features.push(Feature::new(Tag::new('v', 'e', 'r', 't'), 1, ..));
+  }
+  features
+}
+
+fn is_neutral(script: Script) -> bool {
+  script.0 == hb::HB_SCRIPT_COMMON || script.0 == hb::HB_SCRIPT_INHERITED
+}
+
+// https://unicode.org/reports/tr24/
+/// Splits `text` by script, common and inherited chars like spaces, punctuation
+/// and marks go with the run before them, leading ones with the first run.
+fn script_runs(text: &str) -> Vec<(Range<usize>, Script)> {
+  let mut runs: Vec<(Range<usize>, Script)> = vec![];
+  for (i, c) in text.char_indices() {
+    let script = Script(unsafe { hb::hb_unicode_script(hb::hb_unicode_funcs_get_default(), c as u32) });
+    let end = i + c.len_utf8();
+    match runs.last_mut() {
+      Some(&mut (ref mut range, ref mut run_script))
+          if is_neutral(script) || is_neutral(*run_script) || *run_script == script => {
+        if !is_neutral(script) {
+          *run_script = script;
+        }
+        range.end = end;
+      },
+      _ => runs.push((i..end, script)),
+    }
+  }
+  runs
+}
+
+// https://docs.microsoft.com/en-us/typography/opentype/spec/chapter2#feature-list-table
+/// Whether the GSUB table of the face lists the `tag` feature.
+fn has_substitution(data: &[u8], index: u32, tag: &[u8; 4]) -> bool {
+  let lookup = || -> Option<bool> {
+    let gsub = FontFile::new(data, index)?.table(b"GSUB")?;
+    let feature_list = gsub.get(read_u16(gsub, 6)? as usize..)?;
+    let count = read_u16(feature_list, 0)? as usize;
+    Some((0..count).any(|i| feature_list.get(2 + 6 * i..6 + 6 * i) == Some(&tag[..])))
+  };
+  lookup().unwrap_or(false)
+}
+
+#[cfg(test)]
+mod shaper_tests {
+  use super::*;
+  use super::super::sfnt::test_font::*;
+  use csshelper::{SANS_SERIF_FONT_FAMILY};
+  use fonts::system_fonts;
+
+  fn sans_serif() -> Shaper {
+    let property = system_fonts::FontPropertyBuilder::new().family(SANS_SERIF_FONT_FAMILY).build();
+    let (data, index) = system_fonts::get(&property).unwrap();
+    Shaper::new(Arc::new(data), index as u32)
+  }
+
+  fn glyph_ids(glyphs: &[ShapedGlyph]) -> Vec<u32> {
+    glyphs.iter().map(|glyph| glyph.glyph_id).collect()
+  }
+
+  #[test]
+  fn should_kern_pairs() {
+    let shaper = sans_serif();
+    let alone = shaper.shape("A", false, false)[0].x_advance;
+    assert!(shaper.shape("AV", false, false)[0].x_advance < alone);
+  }
+
+  #[test]
+  fn should_substitute_ligatures() {
+    let shaper = sans_serif();
+    let ligature = shaper.shape("fi", false, false);
+    assert_eq!(ligature.len(), 1);
+    assert_ne!(ligature[0].glyph_id, shaper.shape("f", false, false)[0].glyph_id);
+  }
+
+  #[test]
+  fn should_join_arabic_letters() {
+    let shaper = sans_serif();
+    let isolated = shaper.shape("ب", true, false)[0].glyph_id;
+    let joined = shaper.shape("بب", true, false);
+    assert!(joined.iter().all(|glyph| glyph.glyph_id != isolated));
+    // after latin text the arabic letters are still shaped as arabic
+    let mut mixed = glyph_ids(&shaper.shape("aبب", false, false)[1..]);
+    mixed.reverse();
+    assert_eq!(mixed, glyph_ids(&joined));
+  }
+
+  #[test]
+  fn should_split_runs_by_script() {
+    let runs: Vec<Range<usize>> = script_runs("(ab) αβ, c").into_iter().map(|(range, _)| range).collect();
+    assert_eq!(runs, vec![0..5, 5..11, 11..12]);
+    assert_eq!(script_runs("").len(), 0);
+  }
+
+  #[test]
+  fn should_find_gsub_features() {
+    // header with the feature list at 10, then two feature records
+    let mut gsub = vec![0, 1, 0, 0, 0, 0, 0, 10, 0, 0, 0, 2];
+    gsub.extend_from_slice(b"liga\0\0smcp\0\0");
+    let data = build_font(&[(b"GSUB", gsub)]);
+    assert!(has_substitution(&data, 0, b"smcp"));
+    assert!(!has_substitution(&data, 0, b"c2sc"));
+    assert!(!has_substitution(&build_font(&[]), 0, b"smcp"));
+  }
+}
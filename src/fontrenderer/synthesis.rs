@@ -0,0 +1,181 @@
+use euclid::{Point2D, Vector2D};
+use lyon_path::{PathEvent};
+
+use canvas::{FontStyle};
+
+/// Horizontal shift per unit of height of synthetic oblique glyphs, about 14 degrees.
+pub const SYNTHETIC_OBLIQUE_SKEW: f32 = 0.25;
+
+/// Lowercase letters of synthetic small caps are capitals scaled by this.
+pub const SMALL_CAPS_SCALE: f32 = 0.7;
+
+/// Styles faked on the outlines of a face lacking the requested weight or style.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Synthesis {
+  pub bold: bool,
+  pub oblique: bool,
+}
+
+impl Synthesis {
+  pub fn new(weight: u16, style: FontStyle, face_weight: u16, face_style: FontStyle) -> Synthesis {
+    Synthesis {
+      bold: weight >= 600 && face_weight < 600,
+      oblique: style != FontStyle::Normal && face_style == FontStyle::Normal,
+    }
+  }
+
+  /// Extra width of synthetic bold glyphs, a 24th of the em like FreeType.
+  pub fn extra_advance(&self, font_size: f32) -> f32 {
+    if self.bold { font_size / 24.0 } else { 0.0 }
+  }
+
+  /// Applies the synthetic styles to a y up outline of a glyph at `font_size`.
+  pub fn apply(&self, outline: Vec<PathEvent>, font_size: f32) -> Vec<PathEvent> {
+    let outline = if self.bold { embolden(&outline, self.extra_advance(font_size) / 2.0) } else { outline };
+    if self.oblique {
+      outline.into_iter()
+        .map(|e| map_points(e, |p| Point2D::new(p.x + p.y * SYNTHETIC_OBLIQUE_SKEW, p.y)))
+        .collect()
+    } else {
+      outline
+    }
+  }
+}
+
+pub fn map_points<F: FnMut(Point2D<f32>) -> Point2D<f32>>(event: PathEvent, mut f: F) -> PathEvent {
+  match event {
+    PathEvent::MoveTo(p) => PathEvent::MoveTo(f(p)),
+    PathEvent::LineTo(p) => PathEvent::LineTo(f(p)),
+    PathEvent::QuadraticTo(cp, p) => PathEvent::QuadraticTo(f(cp), f(p)),
+    PathEvent::CubicTo(cp1, cp2, p) => PathEvent::CubicTo(f(cp1), f(cp2), f(p)),
+    // glyph outlines are made of curves only
+    PathEvent::Arc(c, r, s, e) => PathEvent::Arc(f(c), r, s, e),
+    PathEvent::Close => PathEvent::Close,
+  }
+}
+
+fn event_points(event: &PathEvent) -> Vec<Point2D<f32>> {
+  match *event {
+    PathEvent::MoveTo(p) | PathEvent::LineTo(p) => vec![p],
+    PathEvent::QuadraticTo(cp, p) => vec![cp, p],
+    PathEvent::CubicTo(cp1, cp2, p) => vec![cp1, cp2, p],
+    _ => vec![],
+  }
+}
+
+// https://www.freetype.org/freetype2/docs/reference/ft2-outline_processing.html#ft_outline_embolden
+/// Moves every point of the outline `strength` away from the contour along
+/// the bisector of its edges, control points included like FreeType.
+pub fn embolden(outline: &[PathEvent], strength: f32) -> Vec<PathEvent> {
+  // the outline orientation tells which side of the edges is outside
+  let area: f32 = contours(outline).iter().map(|contour| {
+    let points: Vec<Point2D<f32>> = contour.iter().flat_map(event_points).collect();
+    (0..points.len()).map(|i| {
+      let (p, q) = (points[i], points[(i + 1) % points.len()]);
+      p.x * q.y - q.x * p.y
+    }).sum::<f32>()
+  }).sum();
+  let clockwise = area < 0.0;
+
+  let mut result = Vec::with_capacity(outline.len());
+  for contour in contours(outline) {
+    let points: Vec<Point2D<f32>> = contour.iter().flat_map(event_points).collect();
+    let shifts: Vec<Vector2D<f32>> = (0..points.len()).map(|i| {
+      let previous = find_distinct(&points, i, points.len() - 1);
+      let next = find_distinct(&points, i, 1);
+      match (previous, next) {
+        (Some(previous), Some(next)) => {
+          let normal = |d: Vector2D<f32>| {
+            let d = d / d.length();
+            if clockwise { Vector2D::new(-d.y, d.x) } else { Vector2D::new(d.y, -d.x) }
+          };
+          let (n_in, n_out) = (normal(points[i] - previous), normal(next - points[i]));
+          // 1 + cos of the turn, skip spikes where the miter would blow up
+          let d = 1.0 + n_in.dot(n_out);
+          if d > 1.0 / 16.0 { (n_in + n_out) * (strength / d) } else { Vector2D::zero() }
+        },
+        _ => Vector2D::zero(),
+      }
+    }).collect();
+
+    let mut shifted = points.iter().zip(shifts).map(|(p, shift)| *p + shift);
+    for event in contour {
+      result.push(map_points(*event, |_| shifted.next().unwrap_or(Point2D::zero())));
+    }
+  }
+  result
+}
+
+/// The previous (`step` of `len - 1`) or next (`step` of 1) point differing from `points[i]`.
+fn find_distinct(points: &[Point2D<f32>], i: usize, step: usize) -> Option<Point2D<f32>> {
+  (1..points.len())
+    .map(|n| points[(i + n * step) % points.len()])
+    .find(|p| *p != points[i])
+}
+
+fn contours(outline: &[PathEvent]) -> Vec<&[PathEvent]> {
+  let mut contours = vec![];
+  let mut start = 0;
+  for (i, event) in outline.iter().enumerate() {
+    match *event {
+      PathEvent::MoveTo(_) if i > start => {
+        contours.push(&outline[start..i]);
+        start = i;
+      },
+      PathEvent::Close => {
+        contours.push(&outline[start..i + 1]);
+        start = i + 1;
+      },
+      _ => {},
+    }
+  }
+  if start < outline.len() {
+    contours.push(&outline[start..]);
+  }
+  contours
+}
+
+#[cfg(test)]
+mod synthesis_tests {
+  use super::*;
+
+  fn square(clockwise: bool) -> Vec<PathEvent> {
+    let mut corners = vec![Point2D::new(0.0, 0.0), Point2D::new(0.0, 10.0), Point2D::new(10.0, 10.0), Point2D::new(10.0, 0.0)];
+    if !clockwise {
+      corners.reverse();
+    }
+    let mut outline = vec![PathEvent::MoveTo(corners[0])];
+    outline.extend(corners[1..].iter().map(|p| PathEvent::LineTo(*p)));
+    outline.push(PathEvent::Close);
+    outline
+  }
+
+  #[test]
+  fn should_synthesize_missing_styles() {
+    let synthesis = Synthesis::new(700, FontStyle::Italic, 400, FontStyle::Normal);
+    assert_eq!(synthesis, Synthesis { bold: true, oblique: true });
+    assert_eq!(Synthesis::new(700, FontStyle::Oblique, 700, FontStyle::Italic), Synthesis::default());
+    assert_eq!(Synthesis::new(400, FontStyle::Normal, 700, FontStyle::Italic), Synthesis::default());
+  }
+
+  #[test]
+  fn embolden_should_grow_both_orientations() {
+    for clockwise in vec![true, false] {
+      let outline = embolden(&square(clockwise), 1.0);
+      assert_eq!(outline.len(), 5);
+      for event in outline {
+        for p in event_points(&event) {
+          assert!(p.x == -1.0 || p.x == 11.0);
+          assert!(p.y == -1.0 || p.y == 11.0);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn oblique_should_skew_to_the_right() {
+    let synthesis = Synthesis { bold: false, oblique: true };
+    let outline = synthesis.apply(vec![PathEvent::MoveTo(Point2D::new(1.0, 8.0))], 16.0);
+    assert_eq!(event_points(&outline[0]), vec![Point2D::new(3.0, 8.0)]);
+  }
+}
@@ -0,0 +1,36 @@
+// https://www.unicode.org/reports/tr50/
+/// Whether `c` stays upright in vertical text (Vertical_Orientation U, Tu and Tr),
+/// transformed chars get their vertical form from the `vert` feature.
+pub fn is_upright(c: char) -> bool {
+  match c as u32 {
+    0x00a7 | 0x00a9 | 0x00ae | 0x00b1 | 0x00bc...0x00be | 0x00d7 | 0x00f7 |
+    0x1100...0x11ff | 0x1400...0x167f | 0x18b0...0x18ff |
+    0x2460...0x24ff | 0x25a0...0x27bf | 0x2e80...0x2fff |
+    0x3000...0x33ff | 0x3400...0x4dbf | 0x4dc0...0x9fff | 0xa000...0xa4cf |
+    0xa960...0xa97f | 0xac00...0xd7ff | 0xe000...0xfaff |
+    0xfe10...0xfe1f | 0xfe30...0xfe6f | 0xff01...0xff60 | 0xffe0...0xffe7 |
+    0x1f000...0x1faff | 0x20000...0x3fffd => true,
+    _ => false,
+  }
+}
+
+#[cfg(test)]
+mod vertical_tests {
+  use super::*;
+
+  #[test]
+  fn cjk_should_be_upright() {
+    assert!(is_upright('漢'));
+    assert!(is_upright('か'));
+    assert!(is_upright('한'));
+    assert!(is_upright('。'));
+    assert!(is_upright('Ａ'));
+  }
+
+  #[test]
+  fn latin_should_be_rotated() {
+    assert!(!is_upright('A'));
+    assert!(!is_upright('1'));
+    assert!(!is_upright(' '));
+  }
+}
@@ -0,0 +1,106 @@
+use base64;
+use image::{ColorType};
+use image::jpeg::{JPEGEncoder};
+use image::png::{PNGEncoder};
+use webp::{Encoder as WebPEncoder};
+
+use super::canvas::{CanvasError, ImageFormat};
+
+/// Encodes premultiplied RGBA pixels into the given format.
+/// Formats without alpha channel are composited onto black, which is what
+/// dropping the alpha of premultiplied pixels does.
+pub fn encode(pixels: &[u8], width: u32, height: u32, format: ImageFormat) -> Result<Vec<u8>, CanvasError> {
+  let expected = (width * height * 4) as usize;
+  if pixels.len() != expected {
+    return Err(CanvasError::InvalidImageData { expected, actual: pixels.len() });
+  }
+
+  let mut buffer = vec![];
+  match format {
+    ImageFormat::Png => {
+      let pixels = unpremultiply(pixels);
+      PNGEncoder::new(&mut buffer).encode(&pixels, width, height, ColorType::RGBA(8))
+        .map_err(|e| CanvasError::Encode(e.to_string()))?;
+    },
+    ImageFormat::Jpeg(quality) => {
+      let rgb: Vec<u8> = pixels.chunks(4).flat_map(|p| p[0..3].to_vec()).collect();
+      JPEGEncoder::new_with_quality(&mut buffer, to_percent(quality))
+        .encode(&rgb, width, height, ColorType::RGB(8))
+        .map_err(|e| CanvasError::Encode(e.to_string()))?;
+    },
+    ImageFormat::WebP(quality) => {
+      let pixels = unpremultiply(pixels);
+      let memory = WebPEncoder::from_rgba(&pixels, width, height).encode(to_percent(quality) as f32);
+      buffer.extend_from_slice(&memory);
+    },
+  };
+  Ok(buffer)
+}
+
+pub fn to_data_url(pixels: &[u8], width: u32, height: u32, format: ImageFormat) -> Result<String, CanvasError> {
+  let buffer = encode(pixels, width, height, format)?;
+  Ok(format!("data:{};base64,{}", format.mime_type(), base64::encode(&buffer)))
+}
+
+fn to_percent(quality: f32) -> u8 {
+  (quality.max(0.0).min(1.0) * 100.0).round() as u8
+}
+
+fn unpremultiply(pixels: &[u8]) -> Vec<u8> {
+  let mut result = Vec::with_capacity(pixels.len());
+  for pixel in pixels.chunks(4) {
+    let alpha = pixel[3] as u16;
+    if alpha == 0 {
+      result.extend_from_slice(&[0, 0, 0, 0]);
+    } else {
+      // add alpha / 2 before dividing for more accurate rounding
+      let unpremultiply_channel = |channel: u8| ((channel as u16 * 255 + alpha / 2) / alpha).min(255) as u8;
+      result.push(unpremultiply_channel(pixel[0]));
+      result.push(unpremultiply_channel(pixel[1]));
+      result.push(unpremultiply_channel(pixel[2]));
+      result.push(pixel[3]);
+    }
+  }
+  result
+}
+
+#[cfg(test)]
+mod image_encoder_tests {
+  use super::*;
+
+  #[test]
+  fn should_unpremultiply_pixels() {
+    assert_eq!(unpremultiply(&[64, 32, 0, 128, 10, 10, 10, 0]), vec![128, 64, 0, 128, 0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn should_encode_png() {
+    let png = encode(&[255, 0, 0, 255], 1, 1, ImageFormat::Png).unwrap();
+    assert_eq!(&png[0..8], &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]);
+  }
+
+  #[test]
+  fn should_encode_jpeg() {
+    let jpeg = encode(&[255, 0, 0, 255], 1, 1, ImageFormat::Jpeg(0.8)).unwrap();
+    assert_eq!(&jpeg[0..2], &[0xff, 0xd8]);
+  }
+
+  #[test]
+  fn should_reject_mismatched_pixels() {
+    assert_eq!(encode(&[0, 0, 0], 1, 1, ImageFormat::Png),
+               Err(CanvasError::InvalidImageData { expected: 4, actual: 3 }));
+  }
+
+  #[test]
+  fn should_build_data_url() {
+    let url = to_data_url(&[0, 0, 0, 0], 1, 1, ImageFormat::Png).unwrap();
+    assert!(url.starts_with("data:image/png;base64,iVBORw0KGgo"));
+  }
+
+  #[test]
+  fn should_fallback_to_png_for_unknown_mime_type() {
+    assert_eq!(ImageFormat::from_mime_type("image/gif", None), ImageFormat::Png);
+    assert_eq!(ImageFormat::from_mime_type("image/jpeg", Some(2.0)), ImageFormat::Jpeg(0.92));
+    assert_eq!(ImageFormat::from_mime_type("IMAGE/WEBP", Some(0.5)), ImageFormat::WebP(0.5));
+  }
+}
@@ -1,16 +1,22 @@
 extern crate app_units;
 extern crate azure;
+extern crate base64;
 extern crate cssparser;
 extern crate euclid;
 extern crate font_loader as fonts;
 extern crate gleam;
+extern crate harfbuzz_rs;
 extern crate glutin;
+extern crate image;
+#[macro_use] extern crate lazy_static;
 extern crate lyon_path;
 extern crate num_traits;
 extern crate pathfinder_font_renderer;
 extern crate serde;
 #[macro_use] extern crate serde_derive;
 extern crate skia;
+extern crate unicode_bidi;
+extern crate webp;
 
 #[cfg(target_os="macos")]
 extern crate cgl;
@@ -22,6 +28,7 @@ extern crate core_foundation;
 mod canvas;
 mod csshelper;
 mod fontrenderer;
+mod imageencoder;
 
 pub use canvas::*;
 
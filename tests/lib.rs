@@ -1,11 +1,301 @@
+extern crate cssparser;
+extern crate euclid;
 extern crate rustcanvas;
 
 #[cfg(test)]
 mod intergration_tests {
-  use rustcanvas::{create_canvas, CanvasContextType};
+  use cssparser::{RGBA};
+  use euclid::{Size2D, Transform2D, Vector2D};
+  use rustcanvas::{create_canvas, CanvasContextType, CanvasGradientStop, ConicGradientStyle, FillOrStrokeStyle, FillRule};
+  use rustcanvas::{LineCapStyle, LinearGradientStyle, Path2D, Repetition, SurfaceStyle};
 
   #[test]
   fn should_create_canvas() {
     create_canvas(1920, 1080, CanvasContextType::CTX2D);
   }
+
+  #[test]
+  fn should_reuse_path2d() {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    let mut square = Path2D::new();
+    square.rect(0.0, 0.0, 10.0, 10.0);
+    let mut shapes = Path2D::new();
+    shapes.add_path(&square, None);
+    shapes.add_path(&square, Some(Transform2D::create_translation(50.0, 50.0)));
+
+    ctx.set_fill_style(FillOrStrokeStyle::Color(RGBA::new(0, 0, 255, 255))).unwrap();
+    ctx.fill_path(&shapes, FillRule::Nonzero).unwrap();
+    assert!(ctx.is_point_in_path2d(&shapes, 55.0, 55.0, FillRule::Nonzero).unwrap());
+    assert!(!ctx.is_point_in_path2d(&shapes, 30.0, 30.0, FillRule::Nonzero).unwrap());
+    // the implicit path is untouched
+    assert!(!ctx.is_point_in_path(5.0, 5.0, FillRule::Nonzero).unwrap());
+
+    let pixels = ctx.get_image_data(55, 55, 1, 1).unwrap();
+    assert_eq!(pixels, vec![0, 0, 255, 255]);
+    let pixels = ctx.get_image_data(30, 30, 1, 1).unwrap();
+    assert_eq!(pixels, vec![0, 0, 0, 0]);
+    ctx.close().unwrap();
+  }
+
+  fn fill_donut(fill_rule: FillRule) -> Vec<u8> {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    let mut donut = Path2D::new();
+    // both squares are drawn clockwise, so nonzero fills the hole while evenodd keeps it
+    donut.rect(10.0, 10.0, 80.0, 80.0);
+    donut.rect(30.0, 30.0, 40.0, 40.0);
+    ctx.set_fill_style(FillOrStrokeStyle::Color(RGBA::new(255, 0, 0, 255))).unwrap();
+    ctx.fill_path(&donut, fill_rule).unwrap();
+    assert!(ctx.is_point_in_path2d(&donut, 20.0, 20.0, fill_rule).unwrap());
+    assert_eq!(ctx.is_point_in_path2d(&donut, 50.0, 50.0, fill_rule).unwrap(), fill_rule == FillRule::Nonzero);
+    let pixels = ctx.get_image_data(50, 50, 1, 1).unwrap();
+    ctx.close().unwrap();
+    pixels
+  }
+
+  #[test]
+  fn should_fill_with_fill_rule() {
+    assert_eq!(fill_donut(FillRule::Nonzero), vec![255, 0, 0, 255]);
+    assert_eq!(fill_donut(FillRule::Evenodd), vec![0, 0, 0, 0]);
+  }
+
+  #[test]
+  fn should_clip_with_fill_rule() {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    ctx.rect(0.0, 0.0, 100.0, 100.0).unwrap();
+    ctx.rect(25.0, 25.0, 50.0, 50.0).unwrap();
+    ctx.clip(FillRule::Evenodd).unwrap();
+    ctx.set_fill_style(FillOrStrokeStyle::Color(RGBA::new(0, 255, 0, 255))).unwrap();
+    ctx.fill_rect(0.0, 0.0, 100.0, 100.0).unwrap();
+    assert_eq!(ctx.get_image_data(10, 10, 1, 1).unwrap(), vec![0, 255, 0, 255]);
+    assert_eq!(ctx.get_image_data(50, 50, 1, 1).unwrap(), vec![0, 0, 0, 0]);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_check_point_in_path_with_fill_rule() {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    ctx.rect(0.0, 0.0, 100.0, 100.0).unwrap();
+    ctx.rect(25.0, 25.0, 50.0, 50.0).unwrap();
+    assert!(ctx.is_point_in_path(50.0, 50.0, FillRule::Nonzero).unwrap());
+    assert!(!ctx.is_point_in_path(50.0, 50.0, FillRule::Evenodd).unwrap());
+    // checking a point keeps the current path
+    assert!(ctx.is_point_in_path(10.0, 10.0, FillRule::Evenodd).unwrap());
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_check_point_in_stroke() {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    ctx.move_to(10.0, 50.0).unwrap();
+    ctx.line_to(90.0, 50.0).unwrap();
+    ctx.set_line_width(10.0).unwrap();
+    assert!(ctx.is_point_in_stroke(50.0, 53.0).unwrap());
+    assert!(!ctx.is_point_in_stroke(50.0, 58.0).unwrap());
+    // butt caps don't extend the line
+    assert!(!ctx.is_point_in_stroke(7.0, 50.0).unwrap());
+    ctx.set_line_cap(LineCapStyle::Square).unwrap();
+    assert!(ctx.is_point_in_stroke(7.0, 50.0).unwrap());
+    ctx.set_line_width(20.0).unwrap();
+    assert!(ctx.is_point_in_stroke(50.0, 58.0).unwrap());
+
+    let mut line = Path2D::new();
+    line.move_to(50.0, 0.0);
+    line.line_to(50.0, 100.0);
+    assert!(ctx.is_point_in_stroke2d(&line, 55.0, 20.0).unwrap());
+    assert!(!ctx.is_point_in_stroke2d(&line, 65.0, 20.0).unwrap());
+    ctx.close().unwrap();
+  }
+
+  fn stroke_dashed_line(offset: f32, cap: LineCapStyle) -> Vec<u8> {
+    let ctx = create_canvas(100, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_stroke_style(FillOrStrokeStyle::Color(RGBA::new(0, 0, 0, 255))).unwrap();
+    ctx.set_line_width(4.0).unwrap();
+    ctx.set_line_cap(cap).unwrap();
+    ctx.set_line_dash(&[10.0]).unwrap();
+    ctx.set_line_dash_offset(offset).unwrap();
+    ctx.move_to(0.0, 5.0).unwrap();
+    ctx.line_to(100.0, 5.0).unwrap();
+    ctx.stroke().unwrap();
+    let row = ctx.get_image_data(0, 5, 100, 1).unwrap();
+    ctx.close().unwrap();
+    row.chunks(4).map(|p| p[3]).collect()
+  }
+
+  #[test]
+  fn should_stroke_with_line_dash() {
+    let alpha = stroke_dashed_line(0.0, LineCapStyle::Butt);
+    assert_eq!(alpha[5], 255);
+    assert_eq!(alpha[15], 0);
+    assert_eq!(alpha[25], 255);
+    let alpha = stroke_dashed_line(5.0, LineCapStyle::Butt);
+    assert_eq!(alpha[2], 255);
+    assert_eq!(alpha[8], 0);
+    assert_eq!(alpha[18], 255);
+  }
+
+  #[test]
+  fn dash_offset_into_a_gap_should_not_draw_dots() {
+    // the line starts 5px before the end of a gap, round caps reach 2px past the dashes
+    let alpha = stroke_dashed_line(15.0, LineCapStyle::Round);
+    assert_eq!(alpha[0], 0);
+    assert_eq!(alpha[10], 255);
+    assert_eq!(alpha[20], 0);
+    assert_eq!(alpha[30], 255);
+  }
+
+  #[test]
+  fn should_save_and_restore_line_dash() {
+    let ctx = create_canvas(10, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_line_dash(&[1.0, 2.0, 3.0]).unwrap();
+    assert_eq!(ctx.get_line_dash().unwrap(), vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0]);
+    ctx.save().unwrap();
+    ctx.set_line_dash(&[4.0, 4.0]).unwrap();
+    ctx.restore().unwrap();
+    assert_eq!(ctx.get_line_dash().unwrap(), vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0]);
+    ctx.close().unwrap();
+  }
+
+  const RED: [u8; 4] = [255, 0, 0, 255];
+  const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+  /// Red for the first half of the gradient, then blue.
+  fn red_then_blue() -> Vec<CanvasGradientStop> {
+    let (red, blue) = (RGBA::new(255, 0, 0, 255), RGBA::new(0, 0, 255, 255));
+    vec![
+      CanvasGradientStop { offset: 0.0, color: red },
+      CanvasGradientStop { offset: 0.5, color: red },
+      CanvasGradientStop { offset: 0.5, color: blue },
+      CanvasGradientStop { offset: 1.0, color: blue },
+    ]
+  }
+
+  fn linear_gradient() -> FillOrStrokeStyle {
+    FillOrStrokeStyle::LinearGradient(LinearGradientStyle::new(0.0, 0.0, 100.0, 0.0, red_then_blue()))
+  }
+
+  #[test]
+  fn gradient_should_use_transform_at_paint_time() {
+    let ctx = create_canvas(100, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_fill_style(linear_gradient()).unwrap();
+    ctx.set_transform(1.0, 0.0, 0.0, 1.0, 30.0, 0.0).unwrap();
+    ctx.fill_rect(-30.0, 0.0, 100.0, 10.0).unwrap();
+    // the middle of the gradient moved from 50 to 80
+    assert_eq!(ctx.get_image_data(70, 5, 1, 1).unwrap(), RED.to_vec());
+    assert_eq!(ctx.get_image_data(90, 5, 1, 1).unwrap(), BLUE.to_vec());
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn gradient_should_follow_restored_transform() {
+    let ctx = create_canvas(100, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_fill_style(linear_gradient()).unwrap();
+    ctx.save().unwrap();
+    ctx.set_transform(0.5, 0.0, 0.0, 1.0, 0.0, 0.0).unwrap();
+    ctx.fill_rect(0.0, 0.0, 200.0, 5.0).unwrap();
+    ctx.restore().unwrap();
+    ctx.fill_rect(0.0, 5.0, 100.0, 5.0).unwrap();
+    assert_eq!(ctx.get_image_data(20, 2, 1, 1).unwrap(), RED.to_vec());
+    assert_eq!(ctx.get_image_data(30, 2, 1, 1).unwrap(), BLUE.to_vec());
+    assert_eq!(ctx.get_image_data(45, 7, 1, 1).unwrap(), RED.to_vec());
+    assert_eq!(ctx.get_image_data(55, 7, 1, 1).unwrap(), BLUE.to_vec());
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn restore_should_bring_back_fill_style_and_transform() {
+    let ctx = create_canvas(100, 10, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_fill_style(FillOrStrokeStyle::ConicGradient(ConicGradientStyle::new(0.0, 50.0, 5.0, red_then_blue()))).unwrap();
+    ctx.save().unwrap();
+    ctx.set_fill_style(linear_gradient()).unwrap();
+    ctx.set_transform(-1.0, 0.0, 0.0, -1.0, 100.0, 10.0).unwrap();
+    ctx.restore().unwrap();
+    ctx.fill_rect(0.0, 0.0, 100.0, 10.0).unwrap();
+    // below the center is the red half of the conic gradient, above it the blue one
+    assert_eq!(ctx.get_image_data(20, 8, 1, 1).unwrap(), RED.to_vec());
+    assert_eq!(ctx.get_image_data(20, 1, 1, 1).unwrap(), BLUE.to_vec());
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_fill_conic_gradient_with_transform() {
+    let ctx = create_canvas(100, 100, CanvasContextType::CTX2D).get_context_2d();
+    // red for the first half turn from the x axis, then blue
+    ctx.set_fill_style(FillOrStrokeStyle::ConicGradient(ConicGradientStyle::new(0.0, 50.0, 50.0, red_then_blue()))).unwrap();
+    ctx.fill_rect(0.0, 0.0, 100.0, 100.0).unwrap();
+    assert_eq!(ctx.get_image_data(75, 60, 1, 1).unwrap(), RED.to_vec());
+    assert_eq!(ctx.get_image_data(25, 40, 1, 1).unwrap(), BLUE.to_vec());
+
+    // turned half a turn around the center of the canvas
+    ctx.set_transform(-1.0, 0.0, 0.0, -1.0, 100.0, 100.0).unwrap();
+    ctx.fill_rect(0.0, 0.0, 100.0, 100.0).unwrap();
+    assert_eq!(ctx.get_image_data(75, 60, 1, 1).unwrap(), BLUE.to_vec());
+    assert_eq!(ctx.get_image_data(25, 40, 1, 1).unwrap(), RED.to_vec());
+    ctx.close().unwrap();
+  }
+
+  fn fill_with_pattern(transform: Transform2D<f32>) -> Vec<u8> {
+    let ctx = create_canvas(20, 20, CanvasContextType::CTX2D).get_context_2d();
+    // 2x2 red pixels in B8G8R8A8
+    let mut pattern = SurfaceStyle::with_repetition((0..4).flat_map(|_| vec![0, 0, 255, 255]).collect(), Size2D::new(2, 2), Repetition::NoRepeat);
+    pattern.set_transform(transform);
+    ctx.set_fill_style(FillOrStrokeStyle::Surface(pattern)).unwrap();
+    ctx.rect(0.0, 0.0, 20.0, 20.0).unwrap();
+    ctx.fill(FillRule::Nonzero).unwrap();
+    let row = ctx.get_image_data(0, 1, 20, 1).unwrap();
+    ctx.close().unwrap();
+    row.chunks(4).map(|p| p[3]).collect()
+  }
+
+  #[test]
+  fn should_fill_path_with_pattern_size_and_transform() {
+    let alpha = fill_with_pattern(Transform2D::identity());
+    assert_eq!((alpha[1], alpha[3]), (255, 0));
+    let alpha = fill_with_pattern(Transform2D::create_scale(3.0, 3.0).post_translate(Vector2D::new(4.0, 0.0)));
+    assert_eq!((alpha[3], alpha[5], alpha[9], alpha[11]), (0, 255, 255, 0));
+  }
+
+  #[test]
+  fn should_not_clip_shadow_to_pattern_area() {
+    let ctx = create_canvas(20, 20, CanvasContextType::CTX2D).get_context_2d();
+    let pattern = SurfaceStyle::with_repetition((0..4).flat_map(|_| vec![0, 0, 255, 255]).collect(), Size2D::new(2, 2), Repetition::NoRepeat);
+    ctx.set_fill_style(FillOrStrokeStyle::Surface(pattern)).unwrap();
+    ctx.set_shadow_color_str("blue").unwrap();
+    ctx.set_shadow_offset_x(10.0).unwrap();
+    ctx.fill_rect(0.0, 0.0, 20.0, 20.0).unwrap();
+    assert_eq!(ctx.get_image_data(1, 1, 1, 1).unwrap(), RED.to_vec());
+    assert_eq!(ctx.get_image_data(11, 1, 1, 1).unwrap(), BLUE.to_vec());
+    assert_eq!(ctx.get_image_data(5, 1, 1, 1).unwrap(), vec![0, 0, 0, 0]);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn should_filter_draw_operations() {
+    let ctx = create_canvas(20, 20, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_fill_style_str("red").unwrap();
+    ctx.set_filter("invert(100%)").unwrap();
+    ctx.fill_rect(0.0, 0.0, 10.0, 10.0).unwrap();
+    assert_eq!(ctx.get_image_data(5, 5, 1, 1).unwrap(), vec![0, 255, 255, 255]);
+    // the layer keeps the transform, the shadow has the offset in pixels
+    ctx.set_filter("drop-shadow(5px 0 blue)").unwrap();
+    ctx.set_transform(1.0, 0.0, 0.0, 1.0, 10.0, 10.0).unwrap();
+    ctx.rect(0.0, 0.0, 5.0, 5.0).unwrap();
+    ctx.fill(FillRule::Nonzero).unwrap();
+    assert_eq!(ctx.get_image_data(12, 12, 1, 1).unwrap(), RED.to_vec());
+    assert_eq!(ctx.get_image_data(17, 12, 1, 1).unwrap(), BLUE.to_vec());
+    assert_eq!(ctx.get_image_data(17, 5, 1, 1).unwrap(), vec![0, 0, 0, 0]);
+    ctx.close().unwrap();
+  }
+
+  #[test]
+  fn filter_layer_should_cover_the_blur() {
+    let ctx = create_canvas(20, 20, CanvasContextType::CTX2D).get_context_2d();
+    ctx.set_fill_style_str("red").unwrap();
+    ctx.set_filter("blur(2px)").unwrap();
+    ctx.fill_rect(8.0, 8.0, 4.0, 4.0).unwrap();
+    // the blur spreads out of the rect, farther than the layer of the rect alone
+    let alpha = ctx.get_image_data(5, 10, 1, 1).unwrap()[3];
+    assert!(alpha > 0 && alpha < 255);
+    assert_eq!(ctx.get_image_data(0, 10, 1, 1).unwrap(), vec![0, 0, 0, 0]);
+    ctx.close().unwrap();
+  }
 }